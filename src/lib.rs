@@ -1 +1,3 @@
 pub mod sender;
+#[cfg(feature = "testing")]
+pub mod testing;
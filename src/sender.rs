@@ -1,7 +1,17 @@
 use std::collections::VecDeque;
-use std::io::{Error as IOError, Write};
-use std::net::{TcpStream, ToSocketAddrs};
-use std::time::{Duration, Instant};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Error as IOError, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+#[cfg(unix)]
+use std::os::unix::net::{SocketAddr as UnixSocketAddr, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Provides retry manager based on error timestamp.
 pub trait RetryManager {
@@ -35,6 +45,7 @@ pub trait RetryManager {
 /// // should wait
 /// assert!(!manager.should_retry(last + Duration::from_millis(10)));
 /// ```
+#[derive(Clone)]
 pub struct ConstantDelay {
     error_records: VecDeque<Instant>,
     max_errors: usize,
@@ -42,105 +53,2358 @@ pub struct ConstantDelay {
 }
 
 impl ConstantDelay {
+    /// Creates a manager which keeps up to 100 errors and waits 50ms.
     pub fn new() -> ConstantDelay {
+        ConstantDelay::with_params(100, Duration::from_millis(50))
+    }
+
+    pub fn with_params(max_errors: usize, wait: Duration) -> ConstantDelay {
         ConstantDelay {
             error_records: VecDeque::new(),
-            max_errors: 100,
-            wait: Duration::from_millis(50),
+            max_errors,
+            wait,
+        }
+    }
+}
+
+impl Default for ConstantDelay {
+    fn default() -> ConstantDelay {
+        ConstantDelay::new()
+    }
+}
+
+impl RetryManager for ConstantDelay {
+
+    fn clear_errors(&mut self) {
+        self.error_records.clear();
+    }
+
+    fn record_error(&mut self, now: Instant) {
+        self.error_records.push_front(now);
+
+        if self.error_records.len() > self.max_errors {
+            self.error_records.pop_back();
+        }
+    }
+
+    fn should_retry(&self, now: Instant) -> bool {
+        match self.error_records.back() {
+            Some(last) => (now - *last) >= self.wait,
+            None => true,
+        }
+    }
+}
+
+/// Provides retry manager which doubles the wait on each consecutive error.
+///
+/// The wait starts from `base` and is capped at `max`. With jitter enabled, the actual wait is
+/// chosen uniformly between zero and the computed wait, so loggers sharing a fluentd don't reconnect
+/// in lockstep.
+///
+/// # Examples
+///
+/// ```
+/// use fluent::sender::{ExponentialBackoff, RetryManager};
+/// use std::time::{Duration, Instant};
+///
+/// let mut manager = ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(30), false);
+/// let now = Instant::now();
+///
+/// // when no error
+/// assert!(manager.should_retry(now));
+///
+/// // first error waits base
+/// manager.record_error(now);
+/// assert!(!manager.should_retry(now + Duration::from_millis(99)));
+/// assert!(manager.should_retry(now + Duration::from_millis(100)));
+///
+/// // second error waits twice as long
+/// manager.record_error(now);
+/// assert!(!manager.should_retry(now + Duration::from_millis(199)));
+/// assert!(manager.should_retry(now + Duration::from_millis(200)));
+///
+/// // reset to base
+/// manager.clear_errors();
+/// assert!(manager.should_retry(now));
+/// ```
+#[derive(Clone)]
+pub struct ExponentialBackoff {
+    base: Duration,
+    max: Duration,
+    jitter: Option<XorShift>,
+    errors: u32,
+    last_error: Option<Instant>,
+    delay: Duration,
+}
+
+impl ExponentialBackoff {
+    pub fn new(base: Duration, max: Duration, jitter: bool) -> ExponentialBackoff {
+        ExponentialBackoff {
+            base,
+            max,
+            jitter: if jitter { Some(XorShift::new()) } else { None },
+            errors: 0,
+            last_error: None,
+            delay: base,
+        }
+    }
+
+    fn backoff(&self) -> Duration {
+        let exp = self.errors.saturating_sub(1).min(31);
+        self.base.checked_mul(1 << exp).unwrap_or(self.max).min(self.max)
+    }
+}
+
+impl RetryManager for ExponentialBackoff {
+
+    fn clear_errors(&mut self) {
+        self.errors = 0;
+        self.last_error = None;
+        self.delay = self.base;
+    }
+
+    fn record_error(&mut self, now: Instant) {
+        self.errors = self.errors.saturating_add(1);
+        self.last_error = Some(now);
+
+        let backoff = self.backoff();
+        self.delay = match self.jitter {
+            Some(ref mut rng) => rng.duration_up_to(backoff),
+            None => backoff,
+        };
+    }
+
+    fn should_retry(&self, now: Instant) -> bool {
+        match self.last_error {
+            Some(last) => now.saturating_duration_since(last) >= self.delay,
+            None => true,
+        }
+    }
+}
+
+/// Provides constant-delay retry manager with random jitter.
+///
+/// After each error the wait is chosen uniformly from `wait * (1 - jitter_fraction)` to
+/// `wait * (1 + jitter_fraction)`, so processes sharing a downed fluentd don't reconnect at the same time.
+///
+/// # Examples
+///
+/// ```
+/// use fluent::sender::{JitteredConstantDelay, RetryManager};
+/// use std::time::{Duration, Instant};
+///
+/// let mut manager = JitteredConstantDelay::new(Duration::from_millis(100), 0.2);
+/// let now = Instant::now();
+///
+/// // when no error
+/// assert!(manager.should_retry(now));
+///
+/// // waits between 80ms and 120ms
+/// manager.record_error(now);
+/// assert!(!manager.should_retry(now + Duration::from_millis(79)));
+/// assert!(manager.should_retry(now + Duration::from_millis(120)));
+/// ```
+#[derive(Clone)]
+pub struct JitteredConstantDelay {
+    wait: Duration,
+    jitter_fraction: f64,
+    rng: XorShift,
+    last_error: Option<Instant>,
+    delay: Duration,
+}
+
+impl JitteredConstantDelay {
+    /// `jitter_fraction` is clamped to `[0, 1]`, and NaN means no jitter.
+    pub fn new(wait: Duration, jitter_fraction: f64) -> JitteredConstantDelay {
+        JitteredConstantDelay {
+            wait,
+            jitter_fraction: if jitter_fraction.is_nan() { 0.0 } else { jitter_fraction.clamp(0.0, 1.0) },
+            rng: XorShift::new(),
+            last_error: None,
+            delay: wait,
+        }
+    }
+}
+
+impl RetryManager for JitteredConstantDelay {
+
+    fn clear_errors(&mut self) {
+        self.last_error = None;
+    }
+
+    fn record_error(&mut self, now: Instant) {
+        let factor = 1.0 + self.jitter_fraction * (2.0 * self.rng.next_f64() - 1.0);
+        self.last_error = Some(now);
+        self.delay = self.wait.mul_f64(factor);
+    }
+
+    fn should_retry(&self, now: Instant) -> bool {
+        match self.last_error {
+            Some(last) => now.saturating_duration_since(last) >= self.delay,
+            None => true,
+        }
+    }
+}
+
+/// State of a `CircuitBreaker`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Sending normally.
+    Closed,
+    /// Too many consecutive errors, sending is skipped until the cooldown passes.
+    Open,
+    /// Cooldown passed, the next attempt is a probe.
+    HalfOpen,
+}
+
+/// Provides retry manager which stops retrying for a while after too many consecutive errors.
+///
+/// The state machine is:
+///
+/// - `Closed`: every attempt is allowed. After `threshold` consecutive errors the circuit opens.
+/// - `Open`: no attempt is allowed until `cooldown` has passed since the circuit opened, then it is half-open.
+/// - `HalfOpen`: one probe is allowed. If it fails the circuit opens again, if it succeeds
+///   (the sender calls `clear_errors`) the circuit closes.
+///
+/// # Examples
+///
+/// ```
+/// use fluent::sender::{CircuitBreaker, CircuitState, RetryManager};
+/// use std::time::{Duration, Instant};
+///
+/// let mut manager = CircuitBreaker::new(2, Duration::from_secs(10));
+/// let now = Instant::now();
+///
+/// manager.record_error(now);
+/// assert!(manager.should_retry(now));
+///
+/// // opens after 2 consecutive errors
+/// manager.record_error(now);
+/// assert_eq!(manager.state(now), CircuitState::Open);
+/// assert!(!manager.should_retry(now + Duration::from_secs(9)));
+///
+/// // probes after cooldown
+/// assert_eq!(manager.state(now + Duration::from_secs(10)), CircuitState::HalfOpen);
+/// assert!(manager.should_retry(now + Duration::from_secs(10)));
+/// ```
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    threshold: usize,
+    cooldown: Duration,
+    consecutive_errors: usize,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: usize, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            consecutive_errors: 0,
+            opened_at: None,
+        }
+    }
+
+    pub fn state(&self, now: Instant) -> CircuitState {
+        match self.opened_at {
+            None => CircuitState::Closed,
+            Some(opened) if now.saturating_duration_since(opened) < self.cooldown => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+}
+
+impl RetryManager for CircuitBreaker {
+
+    fn clear_errors(&mut self) {
+        self.consecutive_errors = 0;
+        self.opened_at = None;
+    }
+
+    fn record_error(&mut self, now: Instant) {
+        self.consecutive_errors = self.consecutive_errors.saturating_add(1);
+
+        // a failed probe opens the circuit again
+        if self.opened_at.is_some() || self.consecutive_errors >= self.threshold {
+            self.opened_at = Some(now);
+        }
+    }
+
+    fn should_retry(&self, now: Instant) -> bool {
+        self.state(now) != CircuitState::Open
+    }
+}
+
+/// Provides retry manager which spends a budget of tokens on retries.
+///
+/// The bucket holds up to `capacity` tokens and starts full. Each error consumes one token, and
+/// retrying is allowed while at least one token is left. Tokens refill at one per `refill_interval`,
+/// so under sustained failure retries are rate limited instead of stopping for a fixed window.
+/// `clear_errors` refills the bucket.
+///
+/// # Examples
+///
+/// ```
+/// use fluent::sender::{RetryManager, TokenBucketRetry};
+/// use std::time::{Duration, Instant};
+///
+/// let mut manager = TokenBucketRetry::new(2, Duration::from_secs(1));
+/// let now = Instant::now();
+///
+/// manager.record_error(now);
+/// assert!(manager.should_retry(now));
+///
+/// // out of tokens until one is refilled
+/// manager.record_error(now);
+/// assert!(!manager.should_retry(now + Duration::from_millis(999)));
+/// assert!(manager.should_retry(now + Duration::from_secs(1)));
+/// ```
+#[derive(Clone)]
+pub struct TokenBucketRetry {
+    capacity: u32,
+    refill_interval: Duration,
+    tokens: u32,
+    // when the next partial token started to refill
+    last_refill: Option<Instant>,
+}
+
+impl TokenBucketRetry {
+    /// Zero capacity is treated as one, so the sender can still retry.
+    pub fn new(capacity: u32, refill_interval: Duration) -> TokenBucketRetry {
+        let capacity = capacity.max(1);
+        TokenBucketRetry {
+            capacity,
+            refill_interval,
+            tokens: capacity,
+            last_refill: None,
+        }
+    }
+
+    /// Number of tokens available at `now`.
+    pub fn tokens(&self, now: Instant) -> u32 {
+        self.refill(now).0
+    }
+
+    /// Returns tokens and refill start at `now`, keeping the progress of a partially refilled token.
+    fn refill(&self, now: Instant) -> (u32, Option<Instant>) {
+        match self.last_refill {
+            None => (self.tokens, None),
+            Some(_) if self.refill_interval == Duration::from_secs(0) => (self.capacity, None),
+            Some(last) => {
+                let elapsed = now.saturating_duration_since(last);
+                let refilled = elapsed.as_nanos() / self.refill_interval.as_nanos();
+                let tokens = (u128::from(self.tokens) + refilled).min(u128::from(self.capacity)) as u32;
+                if tokens == self.capacity {
+                    (tokens, None)
+                } else {
+                    // refilled < capacity here, so this fits into u32
+                    (tokens, Some(last + self.refill_interval * refilled as u32))
+                }
+            },
+        }
+    }
+}
+
+impl RetryManager for TokenBucketRetry {
+
+    fn clear_errors(&mut self) {
+        self.tokens = self.capacity;
+        self.last_refill = None;
+    }
+
+    fn record_error(&mut self, now: Instant) {
+        let (tokens, last_refill) = self.refill(now);
+        self.tokens = tokens.saturating_sub(1);
+        self.last_refill = if self.tokens < self.capacity { Some(last_refill.unwrap_or(now)) } else { None };
+    }
+
+    fn should_retry(&self, now: Instant) -> bool {
+        self.tokens(now) > 0
+    }
+}
+
+/// Small xorshift PRNG for retry jitter. Not suitable for anything security related.
+#[derive(Clone)]
+struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    fn new() -> XorShift {
+        static INSTANCES: AtomicUsize = AtomicUsize::new(0);
+
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos() as u64 ^ d.as_secs()).unwrap_or(0);
+        let instance = INSTANCES.fetch_add(1, Ordering::Relaxed) as u64;
+        let seed = nanos ^ instance.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+
+        // state must never be zero
+        XorShift { state: if seed == 0 { 0x2545_f491_4f6c_dd1d } else { seed } }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Returns a number chosen uniformly from `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a duration chosen uniformly from `[0, max]`.
+    fn duration_up_to(&mut self, max: Duration) -> Duration {
+        let nanos = max.as_nanos().min(u64::MAX as u128) as u64;
+        match nanos.checked_add(1) {
+            Some(bound) => Duration::from_nanos(self.next_u64() % bound),
+            None => Duration::from_nanos(self.next_u64()),
+        }
+    }
+}
+
+/// Tells the sender what to do with unsent data after an error was handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorAction {
+    /// Keep the data buffered and send it once the retry manager allows.
+    Retain,
+    /// Discard the data, for example because the handler stored it elsewhere.
+    Drop,
+    /// Keep the data and clear recorded errors, so the next emit retries immediately.
+    Reset,
+}
+
+/// Provides current time to senders, so that retry timing can be driven by a fake clock in tests.
+pub trait Clock {
+
+    fn now(&self) -> Instant;
+}
+
+/// A Clock backed by `Instant::now`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Provides feature to handle error (for example, log to local file / raise alert, etc)
+///
+/// The returned action decides what happens to the unsent data. Besides failed flushes, buffering
+/// senders also call the handler with `BufferFull` when new data doesn't fit into a buffer full of
+/// unsent data, so returning `Drop` bounds memory usage while fluentd is down.
+pub trait ErrorHandler {
+
+    fn handle_error(&mut self, timestamp: Instant, error: &SenderError, unsent_data: &[u8]) -> ErrorAction;
+}
+
+/// Do nothing when error occurred, and keep unsent data.
+pub struct NullHandler;
+
+impl ErrorHandler for NullHandler {
+
+    fn handle_error(&mut self, _: Instant, _: &SenderError, _: &[u8]) -> ErrorAction { ErrorAction::Retain }
+}
+
+/// Observes a sender on both the success and the failure path, for example to export counters to Prometheus or statsd.
+///
+/// Every method does nothing by default, so implementations only override what they count.
+pub trait Metrics {
+
+    /// Called when `bytes` of data are accepted into the buffer.
+    fn on_emit(&mut self, _bytes: usize) {}
+
+    /// Called when `bytes` of buffered data are written to fluentd.
+    fn on_flush(&mut self, _bytes: usize) {}
+
+    /// Called with each error passed to the error handler.
+    fn on_error(&mut self, _error: &SenderError) {}
+
+    /// Called when the sender opens a new connection to replace a broken one.
+    fn on_reconnect(&mut self) {}
+}
+
+/// Metrics which record nothing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullMetrics;
+
+impl Metrics for NullMetrics {}
+
+/// Receives each frame which a sender gives up on for good, for example to persist or count it.
+///
+/// Unlike `ErrorHandler`, which sees the whole buffer whenever a flush fails, this is called once per
+/// frame, and only when the frame will never be sent: because it is larger than the buffer capacity,
+/// or because `OverflowPolicy::DropOldest` evicted it.
+pub trait DeadLetterHandler {
+
+    /// `tag` is the frame's fluentd tag if the sender knows it. Senders which only see encoded bytes pass `None`.
+    fn on_dead_letter(&mut self, tag: Option<&str>, frame: &[u8]);
+}
+
+/// Spools unsent data to a file so it can be replayed once fluentd recovers.
+///
+/// Each call to `handle_error` appends the unsent data as one record. The spool file survives process
+/// restarts, and once it grows beyond `max_size` bytes the oldest records are evicted first.
+/// If the spool can't be written, or the data alone is larger than `max_size`, the data is kept in
+/// the sender's buffer instead.
+///
+/// Spooled data is dropped from the sender's buffer, so it is only sent again by replay.
+/// To replay, create another handler on the same path.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fluent::sender::{ConstantDelay, FileSpoolHandler, TcpSender};
+///
+/// let spool = FileSpoolHandler::new("/var/spool/fluent.spool", 64 * 1024 * 1024);
+/// let mut sender = TcpSender::new("127.0.0.1:24224", ConstantDelay::new(), spool).unwrap();
+///
+/// // after fluentd recovered
+/// FileSpoolHandler::new("/var/spool/fluent.spool", 64 * 1024 * 1024).replay(&mut sender).unwrap();
+/// ```
+pub struct FileSpoolHandler {
+    path: PathBuf,
+    max_size: u64,
+}
+
+impl FileSpoolHandler {
+    pub fn new<P: AsRef<Path>>(path: P, max_size: u64) -> FileSpoolHandler {
+        FileSpoolHandler {
+            path: path.as_ref().to_path_buf(),
+            max_size,
+        }
+    }
+
+    /// Emits spooled records to `sender` in the order they were spooled, flushing after each one.
+    ///
+    /// Returns the number of replayed records. Records are only removed from the spool once they were
+    /// flushed, so a crash or a failure during replay never loses them, though some may be sent twice.
+    ///
+    /// If the sender's error handler spooled its buffer to this spool when a record failed, that record
+    /// is already in the spool again and is not kept a second time.
+    pub fn replay<S: Sender>(&mut self, sender: &mut S) -> Result<usize, SenderError> {
+        let records = self.read_records()?;
+
+        for (i, record) in records.iter().enumerate() {
+            let size = self.size()?;
+            if let Err(e) = sender.emit(record).and_then(|_| sender.flush()) {
+                let rest = match e {
+                    SenderError::BufferFull => i,
+                    _ if self.size()? != size => i + 1,
+                    _ => i,
+                };
+                self.remove_replayed(records.len(), &records[rest..])?;
+                return Err(e);
+            }
+        }
+        self.remove_replayed(records.len(), &[])?;
+        Ok(records.len())
+    }
+
+    /// Removes the first `replayed` records, then appends `unsent` after anything spooled during replay.
+    fn remove_replayed(&self, replayed: usize, unsent: &[Vec<u8>]) -> Result<(), IOError> {
+        let mut records = self.read_records()?;
+        let mut remaining = records.split_off(replayed.min(records.len()));
+        remaining.extend_from_slice(unsent);
+        self.write_records(&remaining)
+    }
+
+    fn size(&self) -> Result<u64, IOError> {
+        match fs::metadata(&self.path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn append(&self, data: &[u8]) -> Result<(), IOError> {
+        // evicting older records could never make room, so keep the data in the sender instead
+        if data.len() > u32::MAX as usize || 4 + data.len() as u64 > self.max_size {
+            return Err(IOError::new(ErrorKind::InvalidInput, "data is too large to spool"));
+        }
+
+        let size = {
+            let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            file.write_all(&(data.len() as u32).to_be_bytes())?;
+            file.write_all(data)?;
+            file.metadata()?.len()
+        };
+
+        if size > self.max_size {
+            let mut records = self.read_records()?;
+            let mut total = size;
+            // never evict the record just written
+            while total > self.max_size && records.len() > 1 {
+                total -= 4 + records.remove(0).len() as u64;
+            }
+            self.write_records(&records)?;
+        }
+        Ok(())
+    }
+
+    fn read_records(&self) -> Result<Vec<Vec<u8>>, IOError> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut reader = BufReader::new(file);
+        let mut records = Vec::new();
+        let mut len = [0; 4];
+        loop {
+            match reader.read_exact(&mut len) {
+                Ok(_) => (),
+                // end of spool, or a truncated length from an interrupted write
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let mut record = vec![0; u32::from_be_bytes(len) as usize];
+            match reader.read_exact(&mut record) {
+                Ok(_) => records.push(record),
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
         }
+        Ok(records)
+    }
+
+    fn write_records(&self, records: &[Vec<u8>]) -> Result<(), IOError> {
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            for record in records {
+                file.write_all(&(record.len() as u32).to_be_bytes())?;
+                file.write_all(record)?;
+            }
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+impl ErrorHandler for FileSpoolHandler {
+
+    fn handle_error(&mut self, _: Instant, _: &SenderError, unsent_data: &[u8]) -> ErrorAction {
+        match self.append(unsent_data) {
+            Ok(_) => ErrorAction::Drop,
+            Err(_) => ErrorAction::Retain,
+        }
+    }
+}
+
+/// Appends one frame to a buffer for `Sender::emit_with`.
+///
+/// Only appending is possible, so data already in the buffer can't be touched. Encoders which write
+/// to an `io::Write` can write to it directly.
+pub struct FrameWriter<'a> {
+    buf: &'a mut Vec<u8>,
+    start: usize,
+}
+
+impl<'a> FrameWriter<'a> {
+    fn new(buf: &'a mut Vec<u8>) -> FrameWriter<'a> {
+        let start = buf.len();
+        FrameWriter { buf, start }
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        self.buf.push(byte);
+    }
+
+    /// Number of bytes of the frame written so far.
+    pub fn len(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> Write for FrameWriter<'a> {
+    fn write(&mut self, data: &[u8]) -> Result<usize, IOError> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> Result<(), IOError> {
+        Ok(())
+    }
+}
+
+/// Provides feature to send bytes to fluentd.
+pub trait Sender {
+
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError>;
+
+    fn flush(&mut self) -> Result<(), SenderError>;
+
+    /// Emits a frame which `f` writes into the given `FrameWriter`.
+    ///
+    /// Buffering senders append the frame to their own buffer, so it is encoded in place without an
+    /// intermediate allocation and copy. The default implementation encodes into a new `Vec` and calls `emit`.
+    fn emit_with<F: FnOnce(&mut FrameWriter)>(&mut self, f: F) -> Result<(), SenderError> where Self: Sized {
+        let mut data = Vec::new();
+        f(&mut FrameWriter::new(&mut data));
+        self.emit(&data)
+    }
+
+    /// Checks that fluentd is reachable, (re)connecting if needed, without sending any data.
+    ///
+    /// Useful for readiness probes. Senders which can't probe their destination just return `Ok`.
+    fn check(&mut self) -> Result<(), SenderError> {
+        Ok(())
+    }
+
+    /// Emits like `emit`, but sleeps `delay` and tries again while the buffer is full, up to `attempts` times in total.
+    ///
+    /// Only `BufferFull` is retried, since the data was not accepted then. Other errors, such as
+    /// `TooLargeData`, are returned right away. This blocks the calling thread, so it suits simple
+    /// tools which must not lose a log line, not hot paths.
+    fn emit_blocking(&mut self, data: &[u8], attempts: usize, delay: Duration) -> Result<(), SenderError> {
+        let mut attempt = 1;
+        loop {
+            match self.emit(data) {
+                Err(SenderError::BufferFull) if attempt < attempts => (),
+                result => return result,
+            }
+            attempt += 1;
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Errors returned by senders.
+///
+/// `SenderError` is `Send + Sync + 'static`, so it can be passed between threads and boxed into
+/// `Box<dyn Error + Send + Sync>`.
+#[derive(Debug)]
+pub enum SenderError {
+    IO(IOError),
+    /// A single record of `data_len` bytes is larger than the whole buffer capacity, so it will never fit.
+    TooLargeData { data_len: usize, capacity: usize },
+    /// The buffer is full of unsent data. This is transient, so the caller may retry later.
+    BufferFull,
+    /// Writing timed out, for example because fluentd is slow to read. This is transient, so the
+    /// connection is kept and the unsent data is retried later.
+    Timeout(IOError),
+    /// The lock of a `SharedSender` was poisoned by a thread which panicked while holding it,
+    /// or the worker thread of a `BackgroundSender` panicked.
+    Poisoned,
+    /// This many bytes were still buffered when the sender gave up, for example at shutdown.
+    Unflushed(usize),
+}
+
+impl fmt::Display for SenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SenderError::IO(ref e) => write!(f, "failed to send data: {}", e),
+            SenderError::TooLargeData { data_len, capacity } =>
+                write!(f, "data of {} bytes is larger than the buffer capacity of {} bytes", data_len, capacity),
+            SenderError::BufferFull => write!(f, "buffer is full of unsent data"),
+            SenderError::Timeout(ref e) => write!(f, "timed out sending data: {}", e),
+            SenderError::Poisoned => write!(f, "sender lock is poisoned"),
+            SenderError::Unflushed(len) => write!(f, "{} bytes could not be flushed", len),
+        }
+    }
+}
+
+impl Error for SenderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SenderError::IO(ref e) | SenderError::Timeout(ref e) => Some(e),
+            SenderError::TooLargeData { .. } | SenderError::BufferFull | SenderError::Poisoned | SenderError::Unflushed(_) => None,
+        }
+    }
+}
+
+/// Lets `?` convert IO errors, for example in functions which emit and also do other IO.
+///
+/// `WouldBlock` and `TimedOut` become `Timeout`, every other error becomes `IO`.
+impl From<IOError> for SenderError {
+    fn from(e: IOError) -> SenderError {
+        if is_timeout(&e) {
+            SenderError::Timeout(e)
+        } else {
+            SenderError::IO(e)
+        }
+    }
+}
+
+/// A write timeout shows up as `WouldBlock` on Unix and `TimedOut` on Windows.
+fn is_timeout(e: &IOError) -> bool {
+    e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut
+}
+
+/// Decides what a buffering sender does when new data doesn't fit into a buffer full of unsent data.
+///
+/// `RejectNew` keeps memory bounded by refusing the newest data, while `DropOldest` keeps memory
+/// bounded by evicting the oldest frames, so the logs that reach fluentd after an outage are the most
+/// recent ones. Both keep latency low, since `emit` never waits for fluentd.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Ask the error handler what to do with the buffered data, then reject the new data with `BufferFull` if it still doesn't fit.
+    RejectNew,
+    /// Evict the oldest frames until the new data fits.
+    DropOldest,
+}
+
+/// Frames waiting to be sent by a buffering sender, bounded to `max_size` bytes.
+struct FrameBuffer {
+    data: Vec<u8>,
+    max_size: usize,
+    // length of each frame in the buffer, oldest first
+    frames: VecDeque<usize>,
+    // bytes at the head of the buffer already written by a flush which timed out
+    written: usize,
+    // set when a partially written frame was discarded, so the stream is no longer at a frame boundary
+    needs_reconnect: bool,
+    overflow_policy: OverflowPolicy,
+    dead_letter_handler: Option<Box<dyn DeadLetterHandler + Send>>,
+}
+
+impl FrameBuffer {
+    fn new(max_size: usize, overflow_policy: OverflowPolicy) -> FrameBuffer {
+        FrameBuffer {
+            data: Vec::with_capacity(max_size),
+            max_size,
+            frames: VecDeque::new(),
+            written: 0,
+            needs_reconnect: false,
+            overflow_policy,
+            dead_letter_handler: None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    /// Whether `len` more bytes fit without evicting anything.
+    fn fits(&self, len: usize) -> bool {
+        len <= self.max_size.saturating_sub(self.data.len())
+    }
+
+    fn push(&mut self, frame: &[u8]) {
+        self.data.extend_from_slice(frame);
+        self.frames.push_back(frame.len());
+    }
+
+    fn frames<'a>(&'a self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.frames.iter().scan(0, move |offset, len| {
+            let frame = &self.data[*offset..*offset + len];
+            *offset += len;
+            Some(frame)
+        })
+    }
+
+    /// Writes the rest of the buffer, keeping track of how much was written if the write times out.
+    fn write_to<W: Write>(&mut self, writer: &mut W) -> Result<(), IOError> {
+        while self.written < self.data.len() {
+            match writer.write(&self.data[self.written..]) {
+                Ok(0) => return Err(IOError::new(ErrorKind::WriteZero, "failed to write whole buffer")),
+                Ok(n) => self.written += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts writing from the head of the buffer again, on a new connection.
+    fn rewind(&mut self) {
+        self.written = 0;
+        self.needs_reconnect = false;
+    }
+
+    fn clear(&mut self) {
+        if self.written > 0 && self.written < self.data.len() {
+            self.needs_reconnect = true;
+        }
+        self.data.clear();
+        self.frames.clear();
+        self.written = 0;
+    }
+
+    fn dead_letter(&mut self, frame: &[u8]) {
+        if let Some(ref mut handler) = self.dead_letter_handler {
+            handler.on_dead_letter(None, frame);
+        }
+    }
+
+    /// Evicts the oldest frames until `len` more bytes fit into the buffer.
+    fn drop_oldest(&mut self, len: usize) {
+        let mut evicted = 0;
+        while (self.data.len() - evicted).saturating_add(len) > self.max_size {
+            match self.frames.pop_front() {
+                Some(frame) => {
+                    // frames already written by a timed out flush were sent
+                    if evicted + frame > self.written {
+                        if let Some(ref mut handler) = self.dead_letter_handler {
+                            handler.on_dead_letter(None, &self.data[evicted..evicted + frame]);
+                        }
+                    }
+                    evicted += frame;
+                },
+                None => break,
+            }
+        }
+        self.data.drain(..evicted);
+        if evicted > self.written && self.written > 0 {
+            self.needs_reconnect = true;
+        }
+        self.written = self.written.saturating_sub(evicted);
+    }
+}
+
+/// Buffering logic shared by the senders which keep their unsent data in a `FrameBuffer`.
+trait BufferedSender {
+
+    fn frame_buffer(&mut self) -> &mut FrameBuffer;
+
+    fn should_retry(&self, now: Instant) -> bool;
+
+    fn flush_buffer(&mut self) -> Result<(), SenderError>;
+
+    fn handle_error(&mut self, now: Instant, err: &SenderError);
+
+    /// Called for every frame accepted into the buffer.
+    fn on_buffered(&mut self, _len: usize) {}
+
+    /// Makes sure `len` more bytes fit into the buffer, or fails without buffering anything.
+    fn make_room(&mut self, now: Instant, len: usize) -> Result<(), SenderError> {
+        // if data is larger than buffer capacity, it will never fit, so just return error.
+        let max_size = self.frame_buffer().max_size;
+        if len > max_size {
+            Err(SenderError::TooLargeData { data_len: len, capacity: max_size }) ?
+        }
+        let policy = self.frame_buffer().overflow_policy;
+
+        // if buffer space is insufficient, flush first
+        if !self.frame_buffer().fits(len) && self.should_retry(now) {
+            if let Err(e) = self.flush_buffer() {
+                // the error handler has already seen the failure, so only evicting can still make room
+                if !self.frame_buffer().fits(len) && policy == OverflowPolicy::RejectNew {
+                    return Err(e);
+                }
+            }
+        }
+        // if data doesn't fit, make room according to the overflow policy.
+        if !self.frame_buffer().fits(len) {
+            match policy {
+                OverflowPolicy::RejectNew => self.handle_error(now, &SenderError::BufferFull),
+                OverflowPolicy::DropOldest => self.frame_buffer().drop_oldest(len),
+            }
+            if !self.frame_buffer().fits(len) {
+                Err(SenderError::BufferFull) ?
+            }
+        }
+        Ok(())
+    }
+
+    /// Buffers `frames` together, or none of them, then flushes if the retry manager allows it.
+    fn buffer_frames(&mut self, now: Instant, frames: &[&[u8]]) -> Result<(), SenderError> {
+        let len = frames.iter().fold(0usize, |total, frame| total.saturating_add(frame.len()));
+        if let Err(e) = self.make_room(now, len) {
+            if let SenderError::TooLargeData { capacity, .. } = e {
+                // frames which only don't fit together may still be sent separately
+                for frame in frames.iter().filter(|frame| frame.len() > capacity) {
+                    self.frame_buffer().dead_letter(frame);
+                }
+            }
+            return Err(e);
+        }
+
+        for frame in frames {
+            self.frame_buffer().push(frame);
+            self.on_buffered(frame.len());
+        }
+        if self.should_retry(now) {
+            self.flush_buffer()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Builds a `TcpSender` with custom socket and buffer options.
+///
+/// The options are also applied when the sender reconnects.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fluent::sender::{ConstantDelay, NullHandler, TcpSenderBuilder};
+/// use std::time::Duration;
+///
+/// let sender = TcpSenderBuilder::new()
+///     .connect_timeout(Duration::from_secs(3))
+///     .write_timeout(Duration::from_secs(5))
+///     .nodelay(true)
+///     .buffer_capacity(1024 * 1024)
+///     .build("127.0.0.1:24224", ConstantDelay::new(), NullHandler)
+///     .unwrap();
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct TcpSenderBuilder {
+    connect_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    nodelay: bool,
+    buffer_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    reconnect_attempts: usize,
+    reconnect_delay: Duration,
+}
+
+impl TcpSenderBuilder {
+    /// Creates a builder with no timeouts, Nagle's algorithm enabled, 8MB buffer and a single reconnect attempt.
+    pub fn new() -> TcpSenderBuilder {
+        TcpSenderBuilder {
+            connect_timeout: None,
+            write_timeout: None,
+            nodelay: false,
+            buffer_capacity: 8 * 1024 * 1024, // 8MB
+            overflow_policy: OverflowPolicy::RejectNew,
+            reconnect_attempts: 1,
+            reconnect_delay: Duration::from_millis(50),
+        }
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> TcpSenderBuilder {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Duration) -> TcpSenderBuilder {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: bool) -> TcpSenderBuilder {
+        self.nodelay = nodelay;
+        self
+    }
+
+    pub fn buffer_capacity(mut self, capacity: usize) -> TcpSenderBuilder {
+        self.buffer_capacity = capacity;
+        self
+    }
+
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> TcpSenderBuilder {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// How many times a broken connection is reconnected before giving up. Zero is treated as one.
+    pub fn reconnect_attempts(mut self, attempts: usize) -> TcpSenderBuilder {
+        self.reconnect_attempts = attempts;
+        self
+    }
+
+    /// How long to wait between reconnect attempts. Defaults to 50ms.
+    pub fn reconnect_delay(mut self, delay: Duration) -> TcpSenderBuilder {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    /// Connects and builds the sender. Fails with `InvalidInput` if the buffer capacity is zero.
+    pub fn build<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler>(self, addr: A, retry_manager: R, error_handler: H) -> Result<TcpSender<A, R, H>, IOError> {
+        self.build_with_clock(addr, retry_manager, error_handler, SystemClock)
+    }
+
+    /// Same as `build`, but the sender reads the current time from `clock`.
+    pub fn build_with_clock<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler, C: Clock>(self, addr: A, retry_manager: R, error_handler: H, clock: C) -> Result<TcpSender<A, R, H, C>, IOError> {
+        if self.buffer_capacity == 0 {
+            return Err(IOError::new(ErrorKind::InvalidInput, "buffer capacity must not be zero"));
+        }
+
+        self.connect(addr).map(|stream| {
+            TcpSender {
+                addr,
+                options: self,
+                stream,
+                retry_manager,
+                buffer: FrameBuffer::new(self.buffer_capacity, self.overflow_policy),
+                deadline: None,
+                error_handler,
+                clock,
+                metrics: Box::new(NullMetrics),
+                on_reconnect: None,
+            }
+        })
+    }
+
+    /// Connects to the first reachable endpoint and builds a `FailoverSender`. Fails with `InvalidInput` if the buffer capacity is zero.
+    ///
+    /// The options apply to every endpoint, including when the sender fails back to a higher-priority one.
+    pub fn build_failover<A: ToSocketAddrs + Copy, R: RetryManager + Clone, H: ErrorHandler>(self, addrs: &[A], retry_manager: R, error_handler: H) -> Result<FailoverSender<A, R, H>, IOError> {
+        if self.buffer_capacity == 0 {
+            return Err(IOError::new(ErrorKind::InvalidInput, "buffer capacity must not be zero"));
+        }
+
+        let mut sender = FailoverSender {
+            endpoints: addrs.iter().map(|addr| (*addr, retry_manager.clone())).collect(),
+            options: self,
+            current: None,
+            error_handler,
+            buffer: FrameBuffer::new(self.buffer_capacity, self.overflow_policy),
+        };
+
+        let mut last_error = IOError::new(ErrorKind::InvalidInput, "no endpoints given");
+        for i in 0..sender.endpoints.len() {
+            match self.connect(sender.endpoints[i].0) {
+                Ok(stream) => {
+                    sender.current = Some((i, stream));
+                    return Ok(sender);
+                },
+                Err(e) => {
+                    sender.endpoints[i].1.record_error(Instant::now());
+                    last_error = e;
+                },
+            }
+        }
+        Err(last_error)
+    }
+
+    fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<TcpStream, IOError> {
+        let stream = match self.connect_timeout {
+            Some(timeout) => {
+                let mut last_error = None;
+                let mut connected = None;
+                for a in addr.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&a, timeout) {
+                        Ok(s) => {
+                            connected = Some(s);
+                            break;
+                        },
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                match connected {
+                    Some(s) => s,
+                    None => return Err(last_error.unwrap_or_else(|| {
+                        IOError::new(ErrorKind::InvalidInput, "could not resolve to any addresses")
+                    })),
+                }
+            },
+            None => TcpStream::connect(addr)?,
+        };
+        stream.set_nodelay(self.nodelay)?;
+        stream.set_write_timeout(self.write_timeout)?;
+        Ok(stream)
+    }
+}
+
+impl Default for TcpSenderBuilder {
+    fn default() -> TcpSenderBuilder {
+        TcpSenderBuilder::new()
+    }
+}
+
+/// Returns false if the peer closed the connection or the socket has a pending error.
+fn connection_alive(stream: &TcpStream) -> bool {
+    match stream.take_error() {
+        Ok(None) => (),
+        _ => return false,
+    }
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+
+    let mut buf = [0; 1];
+    let alive = match stream.peek(&mut buf) {
+        // closed by peer
+        Ok(0) => false,
+        Ok(_) => true,
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => true,
+        Err(_) => false,
+    };
+    stream.set_nonblocking(false).is_ok() && alive
+}
+
+type ReconnectHook = Box<dyn FnMut(&TcpStream) + Send>;
+
+/// A Sender implementation via TCP.
+///
+/// Buffered data is flushed once, on a best-effort basis, when the sender is dropped.
+/// Call `shutdown` to keep retrying for a bounded time instead.
+///
+/// Every reconnect resolves `addr` again, so passing a host name such as `"fluentd.local:24224"`
+/// follows DNS changes after a fluentd failover. Passing a pre-resolved `SocketAddr` disables
+/// re-resolution, and the sender always reconnects to the same IP.
+///
+/// IPv6 endpoints work like any other address, either as a bracketed string such as `"[::1]:24224"`
+/// or as a `SocketAddrV6` or `(Ipv6Addr, u16)` from service discovery.
+///
+/// Delivery is at-least-once. If the connection breaks while the buffer is being written, the sender
+/// reconnects and writes the whole buffer again, so fluentd may receive the frames written before
+/// the failure twice. Without fluentd's ack responses there is no way to know how much it received.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fluent::sender::{ConstantDelay, Sender, TcpSender, NullHandler};
+///
+/// let mut sender = TcpSender::new("127.0.0.1:24224", ConstantDelay::new(), NullHandler).unwrap();
+///
+/// sender.emit("[\"foo.bar\",1500564758,{\"key\":\"value\"}]".as_bytes());
+/// ```
+pub struct TcpSender<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler, C: Clock = SystemClock> {
+    addr: A,
+    options: TcpSenderBuilder,
+    stream: TcpStream,
+    retry_manager: R,
+    error_handler: H,
+    buffer: FrameBuffer,
+    // set while shutdown is running
+    deadline: Option<Instant>,
+    clock: C,
+    metrics: Box<dyn Metrics + Send>,
+    on_reconnect: Option<ReconnectHook>,
+}
+
+impl<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler> TcpSender<A, R, H> {
+    /// Connects with default options. See `TcpSenderBuilder` to customize them.
+    pub fn new(addr: A, retry_manager: R, error_handler: H) -> Result<TcpSender<A, R, H>, IOError> {
+        TcpSenderBuilder::new().build(addr, retry_manager, error_handler)
+    }
+
+    /// Connects with default options and a buffer of `capacity` bytes.
+    pub fn with_capacity(addr: A, capacity: usize, retry_manager: R, error_handler: H) -> Result<TcpSender<A, R, H>, IOError> {
+        TcpSenderBuilder::new().buffer_capacity(capacity).build(addr, retry_manager, error_handler)
+    }
+}
+
+impl<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler, C: Clock> TcpSender<A, R, H, C> {
+
+    /// Replaces the connection with a fresh one, trying up to `reconnect_attempts` times.
+    ///
+    /// Useful after a known fluentd restart, instead of waiting for the next write to fail. The old
+    /// connection is only closed once the new one is established. If every attempt fails, the old
+    /// connection is kept and the next flush reconnects again when writing to it fails.
+    pub fn reconnect(&mut self) -> Result<(), IOError> {
+        let mut attempt = 1;
+        loop {
+            // during shutdown, don't wait for a connection past the deadline
+            let mut options = self.options;
+            if let Some(left) = self.time_left() {
+                if left == Duration::from_secs(0) {
+                    return Err(IOError::new(ErrorKind::TimedOut, "shutdown deadline passed"));
+                }
+                options.connect_timeout = Some(options.connect_timeout.map_or(left, |timeout| timeout.min(left)));
+            }
+
+            match options.connect(self.addr) {
+                Ok(new_stream) => {
+                    self.stream = new_stream;
+                    self.buffer.rewind();
+                    self.metrics.on_reconnect();
+                    if let Some(ref mut f) = self.on_reconnect {
+                        f(&self.stream);
+                    }
+                    return Ok(());
+                },
+                Err(e) => if attempt >= self.options.reconnect_attempts {
+                    return Err(e);
+                },
+            }
+            attempt += 1;
+            thread::sleep(self.time_left().map_or(self.options.reconnect_delay, |left| left.min(self.options.reconnect_delay)));
+        }
+    }
+
+    /// Time left until the shutdown deadline, or `None` when not shutting down.
+    fn time_left(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(self.clock.now()))
+    }
+
+    fn send_buffer_with_reconnect(&mut self) -> Result<(), IOError> {
+        if self.buffer.needs_reconnect {
+            self.reconnect()?;
+        }
+        match self.buffer.write_to(&mut self.stream) {
+            // a slow fluentd is not a broken connection, so resume on the next flush
+            Err(e) if is_timeout(&e) => Err(e),
+            Err(_) => {
+                self.reconnect()?;
+                self.buffer.write_to(&mut self.stream)
+            },
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Replaces the metrics observing this sender. `NullMetrics` is used by default.
+    pub fn set_metrics<M: Metrics + Send + 'static>(&mut self, metrics: M) {
+        self.metrics = Box::new(metrics);
+    }
+
+    /// Calls `f` with the new connection every time the sender reconnects, for example to log it or to re-apply socket options.
+    pub fn set_on_reconnect<F: FnMut(&TcpStream) + Send + 'static>(&mut self, f: F) {
+        self.on_reconnect = Some(Box::new(f));
+    }
+
+    /// Passes frames which will never be sent to `handler`. Without one, they are just discarded.
+    pub fn set_dead_letter_handler<D: DeadLetterHandler + Send + 'static>(&mut self, handler: D) {
+        self.buffer.dead_letter_handler = Some(Box::new(handler));
+    }
+
+    /// Keeps flushing, as often as the retry manager allows, until the buffer is empty or `timeout` has passed.
+    ///
+    /// Dropping the sender only makes a single flush attempt, so call this before exiting to drain pending
+    /// logs without hanging forever when fluentd is unreachable. On timeout, returns `Unflushed` with the
+    /// number of bytes left in the buffer. Connect and write timeouts and reconnect delays are capped by
+    /// the time left, so it returns soon after `timeout` has passed.
+    ///
+    /// The deadline is measured with the sender's clock, so the clock has to advance for this to return.
+    pub fn shutdown(&mut self, timeout: Duration) -> Result<(), SenderError> {
+        let deadline = self.clock.now() + timeout;
+        self.deadline = Some(deadline);
+        let result = self.drain(deadline);
+        self.deadline = None;
+        result
+    }
+
+    fn drain(&mut self, deadline: Instant) -> Result<(), SenderError> {
+        loop {
+            if self.buffer.is_empty() {
+                return Ok(());
+            }
+            let now = self.clock.now();
+            if now >= deadline {
+                return Err(SenderError::Unflushed(self.buffer.len()));
+            }
+            if self.retry_manager.should_retry(now) {
+                let left = deadline - now;
+                let _ = self.flush_with_timeout(self.options.write_timeout.map_or(left, |timeout| timeout.min(left)));
+            } else {
+                thread::sleep((deadline - now).min(Duration::from_millis(10)));
+            }
+        }
+    }
+
+    /// Flushes like `flush`, but with a write timeout of `timeout` for this call only.
+    ///
+    /// Useful to be more patient at shutdown than in steady state. The configured write timeout is
+    /// restored afterwards, also when the flush fails. A zero `timeout` fails with `InvalidInput`.
+    pub fn flush_with_timeout(&mut self, timeout: Duration) -> Result<(), SenderError> {
+        let default = self.options.write_timeout;
+        // reconnects during this flush apply the options too
+        self.options.write_timeout = Some(timeout);
+        let result = match self.stream.set_write_timeout(Some(timeout)) {
+            Ok(_) => self.flush_buffer(),
+            Err(e) => Err(SenderError::IO(e)),
+        };
+
+        self.options.write_timeout = default;
+        let restored = self.stream.set_write_timeout(default);
+        result.and(restored.map_err(SenderError::IO))
+    }
+
+    /// The current connection, for example to set socket options such as TTL at runtime.
+    ///
+    /// Options set here are lost when the sender reconnects, so re-apply them in a callback registered
+    /// with `set_on_reconnect`. Don't make the stream non-blocking, since the sender relies on blocking writes.
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Number of bytes waiting in the buffer.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Number of frames waiting in the buffer.
+    pub fn frames_buffered(&self) -> usize {
+        self.buffer.frames.len()
+    }
+
+    /// Emits several frames as one unit: either all of them are buffered, or none are.
+    ///
+    /// Useful when partial delivery is worse than none, such as a set of audit records. The frames are
+    /// buffered together, so they are always sent in the same flush. If they don't fit into the buffer
+    /// together, nothing is buffered and `BufferFull` is returned, or `TooLargeData` if their total size
+    /// exceeds the buffer capacity. Only frames which are larger than the buffer capacity by themselves
+    /// are passed to the dead letter handler.
+    pub fn emit_batch(&mut self, frames: &[&[u8]]) -> Result<(), SenderError> {
+        let now = self.clock.now();
+        self.buffer_frames(now, frames)
+    }
+
+    /// Iterates over the frames waiting in the buffer, oldest first.
+    pub fn pending_frames<'a>(&'a self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.buffer.frames()
+    }
+
+}
+
+impl<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler, C: Clock> BufferedSender for TcpSender<A, R, H, C> {
+    fn frame_buffer(&mut self) -> &mut FrameBuffer {
+        &mut self.buffer
+    }
+
+    fn should_retry(&self, now: Instant) -> bool {
+        self.retry_manager.should_retry(now)
+    }
+
+    fn handle_error(&mut self, now: Instant, err: &SenderError) {
+        self.metrics.on_error(err);
+        match self.error_handler.handle_error(now, err, self.buffer.as_slice()) {
+            ErrorAction::Retain => (),
+            ErrorAction::Drop => self.buffer.clear(),
+            ErrorAction::Reset => self.retry_manager.clear_errors(),
+        }
+    }
+
+    fn on_buffered(&mut self, len: usize) {
+        self.metrics.on_emit(len);
+    }
+
+    fn flush_buffer(&mut self) -> Result<(), SenderError> {
+        if self.buffer.is_empty() {
+            self.retry_manager.clear_errors();
+            Ok(())
+        } else {
+            match self.send_buffer_with_reconnect() {
+                Err(e) => {
+                    let now = self.clock.now();
+                    let err = SenderError::from(e);
+                    self.retry_manager.record_error(now);
+                    self.handle_error(now, &err);
+                    Err(err)
+                },
+                Ok(_) => {
+                    self.metrics.on_flush(self.buffer.len());
+                    self.buffer.clear();
+                    self.retry_manager.clear_errors();
+                    Ok(())
+                },
+            }
+        }
+    }
+}
+
+impl<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler, C: Clock> Sender for TcpSender<A, R, H, C> {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        let now = self.clock.now();
+        self.buffer_frames(now, &[data])
+    }
+
+    fn emit_with<F: FnOnce(&mut FrameWriter)>(&mut self, f: F) -> Result<(), SenderError> {
+        let now = self.clock.now();
+        let start = self.buffer.len();
+        f(&mut FrameWriter::new(&mut self.buffer.data));
+
+        // if it doesn't fit, take the frame back out and go through the usual checks
+        if self.buffer.len() > self.buffer.max_size {
+            let frame = self.buffer.data.split_off(start);
+            return self.buffer_frames(now, &[&frame]);
+        }
+
+        let len = self.buffer.len() - start;
+        self.buffer.frames.push_back(len);
+        self.on_buffered(len);
+        if self.should_retry(now) {
+            self.flush_buffer()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), SenderError> {
+        self.flush_buffer()
+    }
+
+    fn check(&mut self) -> Result<(), SenderError> {
+        if connection_alive(&self.stream) {
+            return Ok(());
+        }
+        self.reconnect().map_err(SenderError::IO)
+    }
+}
+
+/// Flushes buffered data on a best-effort basis. Errors are passed to the error handler and otherwise ignored.
+impl<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler, C: Clock> Drop for TcpSender<A, R, H, C> {
+    fn drop(&mut self) {
+        let _ = self.flush_buffer();
+    }
+}
+
+/// A Sender implementation via TCP which fails over between several fluentd endpoints.
+///
+/// Endpoints are ordered by priority. Each endpoint has its own clone of the retry manager, and every
+/// flush tries the endpoints in order, skipping those whose retry manager says to wait. So once the
+/// primary is allowed to retry again it is probed first, and the sender fails back when it recovers.
+/// The error handler is only called when no endpoint accepted the data.
+///
+/// Probing an endpoint blocks the flush until it connects or fails, so set a connect timeout with
+/// `TcpSenderBuilder::build_failover` if a primary may become unreachable without refusing connections.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fluent::sender::{ConstantDelay, NullHandler, Sender, TcpSenderBuilder};
+/// use std::time::Duration;
+///
+/// let mut sender = TcpSenderBuilder::new()
+///     .connect_timeout(Duration::from_secs(1))
+///     .write_timeout(Duration::from_secs(5))
+///     .build_failover(&["10.0.0.1:24224", "10.0.0.2:24224"], ConstantDelay::new(), NullHandler)
+///     .unwrap();
+///
+/// sender.emit("[\"foo.bar\",1500564758,{\"key\":\"value\"}]".as_bytes());
+/// ```
+pub struct FailoverSender<A: ToSocketAddrs + Copy, R: RetryManager + Clone, H: ErrorHandler> {
+    endpoints: Vec<(A, R)>,
+    options: TcpSenderBuilder,
+    current: Option<(usize, TcpStream)>,
+    error_handler: H,
+    buffer: FrameBuffer,
+}
+
+impl<A: ToSocketAddrs + Copy, R: RetryManager + Clone, H: ErrorHandler> FailoverSender<A, R, H> {
+    /// Connects to the first reachable endpoint with default options. Fails only if none of them is reachable.
+    ///
+    /// See `TcpSenderBuilder::build_failover` to set timeouts.
+    pub fn new(addrs: &[A], retry_manager: R, error_handler: H) -> Result<FailoverSender<A, R, H>, IOError> {
+        TcpSenderBuilder::new().build_failover(addrs, retry_manager, error_handler)
+    }
+
+    fn send_buffer_to(&mut self, i: usize) -> Result<(), IOError> {
+        let mut stream = match self.current.take() {
+            Some((current, stream)) if current == i => stream,
+            // the current connection is only replaced once the other endpoint is reachable
+            other => match self.options.connect(self.endpoints[i].0) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    self.current = other;
+                    return Err(e);
+                },
+            },
+        };
+        stream.write_all(self.buffer.as_slice())?;
+        self.current = Some((i, stream));
+        Ok(())
+    }
+}
+
+impl<A: ToSocketAddrs + Copy, R: RetryManager + Clone, H: ErrorHandler> BufferedSender for FailoverSender<A, R, H> {
+    fn frame_buffer(&mut self) -> &mut FrameBuffer {
+        &mut self.buffer
+    }
+
+    fn should_retry(&self, now: Instant) -> bool {
+        self.endpoints.iter().any(|endpoint| endpoint.1.should_retry(now))
+    }
+
+    fn handle_error(&mut self, now: Instant, err: &SenderError) {
+        match self.error_handler.handle_error(now, err, self.buffer.as_slice()) {
+            ErrorAction::Retain => (),
+            ErrorAction::Drop => self.buffer.clear(),
+            ErrorAction::Reset => {
+                for endpoint in &mut self.endpoints {
+                    endpoint.1.clear_errors();
+                }
+            },
+        }
+    }
+
+    fn flush_buffer(&mut self) -> Result<(), SenderError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut last_error = None;
+        for i in 0..self.endpoints.len() {
+            if !self.endpoints[i].1.should_retry(now) {
+                continue;
+            }
+            match self.send_buffer_to(i) {
+                Ok(_) => {
+                    self.buffer.clear();
+                    self.endpoints[i].1.clear_errors();
+                    return Ok(());
+                },
+                Err(e) => {
+                    self.endpoints[i].1.record_error(now);
+                    last_error = Some(e);
+                },
+            }
+        }
+
+        match last_error {
+            Some(e) => {
+                let err = SenderError::IO(e);
+                self.handle_error(now, &err);
+                Err(err)
+            },
+            // every endpoint is waiting, keep the data buffered
+            None => Ok(()),
+        }
+    }
+}
+
+impl<A: ToSocketAddrs + Copy, R: RetryManager + Clone, H: ErrorHandler> Sender for FailoverSender<A, R, H> {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        self.buffer_frames(Instant::now(), &[data])
+    }
+
+    fn flush(&mut self) -> Result<(), SenderError> {
+        self.flush_buffer()
+    }
+
+    /// Checks the current connection. If it is broken, connects to the first reachable endpoint in priority order.
+    fn check(&mut self) -> Result<(), SenderError> {
+        if let Some((_, ref stream)) = self.current {
+            if connection_alive(stream) {
+                return Ok(());
+            }
+        }
+
+        let mut last_error = IOError::new(ErrorKind::InvalidInput, "no endpoints given");
+        for i in 0..self.endpoints.len() {
+            match self.options.connect(self.endpoints[i].0) {
+                Ok(stream) => {
+                    self.current = Some((i, stream));
+                    return Ok(());
+                },
+                Err(e) => last_error = e,
+            }
+        }
+        Err(SenderError::IO(last_error))
+    }
+}
+
+/// Flushes buffered data on a best-effort basis. Errors are passed to the error handler and otherwise ignored.
+impl<A: ToSocketAddrs + Copy, R: RetryManager + Clone, H: ErrorHandler> Drop for FailoverSender<A, R, H> {
+    fn drop(&mut self) {
+        let _ = self.flush_buffer();
+    }
+}
+
+/// Runs `f` on every sender, returning the first error after all of them were tried.
+fn for_each_sender<S, F: FnMut(&mut S) -> Result<(), SenderError>>(senders: &mut [S], mut f: F) -> Result<(), SenderError> {
+    let mut result = Ok(());
+    for sender in senders {
+        let r = f(sender);
+        if result.is_ok() {
+            result = r;
+        }
+    }
+    result
+}
+
+/// A Sender which spreads data across several senders, rotating the target on each `emit`.
+///
+/// Each inner sender keeps its own buffer and retry state. When an inner sender fails, its error is
+/// returned as is and the data is not re-sent to another sender, since a buffering sender such as
+/// `TcpSender` keeps the data and will send it once it recovers.
+///
+/// # Examples
+///
+/// ```
+/// use fluent::sender::{RoundRobinSender, Sender, WriterSender};
+///
+/// let mut sender = RoundRobinSender::new(vec![WriterSender::new(Vec::new()), WriterSender::new(Vec::new())]);
+/// sender.emit(b"foo").unwrap();
+/// sender.emit(b"bar").unwrap();
+///
+/// let senders = sender.into_inner();
+/// assert_eq!(senders[0].get_ref().as_slice(), b"foo");
+/// assert_eq!(senders[1].get_ref().as_slice(), b"bar");
+/// ```
+pub struct RoundRobinSender<S: Sender> {
+    senders: Vec<S>,
+    next: usize,
+}
+
+impl<S: Sender> RoundRobinSender<S> {
+    pub fn new(senders: Vec<S>) -> RoundRobinSender<S> {
+        RoundRobinSender { senders, next: 0 }
+    }
+
+    pub fn into_inner(self) -> Vec<S> {
+        self.senders
+    }
+}
+
+impl<S: Sender> Sender for RoundRobinSender<S> {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        if self.senders.is_empty() {
+            Err(SenderError::IO(IOError::new(ErrorKind::NotConnected, "no senders given"))) ?
+        }
+
+        let i = self.next;
+        self.next = (i + 1) % self.senders.len();
+        self.senders[i].emit(data)
+    }
+
+    /// Flushes every sender, returning the first error after all of them were tried.
+    fn flush(&mut self) -> Result<(), SenderError> {
+        for_each_sender(&mut self.senders, |sender| sender.flush())
+    }
+
+    /// Checks every sender, returning the first error after all of them were tried.
+    fn check(&mut self) -> Result<(), SenderError> {
+        for_each_sender(&mut self.senders, |sender| sender.check())
+    }
+}
+
+/// A Sender which sends the same data to several senders, for example while migrating between fluentd instances.
+///
+/// `emit` and `flush` are always forwarded to every sender, so one failing sender doesn't keep the
+/// others from receiving data. If any of them failed, the first error is returned.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fluent::sender::{ConstantDelay, FileSender, NullHandler, Sender, TcpSender, TeeSender};
+///
+/// let mut sender = TeeSender::new(vec![
+///     Box::new(TcpSender::new("127.0.0.1:24224", ConstantDelay::new(), NullHandler).unwrap()),
+///     Box::new(FileSender::new("/var/log/app/fluent.log", 100 * 1024 * 1024).unwrap()),
+/// ]);
+///
+/// sender.emit("[\"foo.bar\",1500564758,{\"key\":\"value\"}]".as_bytes());
+/// ```
+pub struct TeeSender {
+    senders: Vec<Box<dyn Sender>>,
+}
+
+impl TeeSender {
+    pub fn new(senders: Vec<Box<dyn Sender>>) -> TeeSender {
+        TeeSender { senders }
     }
 }
 
-impl RetryManager for ConstantDelay {
+impl Sender for TeeSender {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        for_each_sender(&mut self.senders, |sender| sender.emit(data))
+    }
 
-    fn clear_errors(&mut self) {
-        self.error_records.clear();
+    fn flush(&mut self) -> Result<(), SenderError> {
+        for_each_sender(&mut self.senders, |sender| sender.flush())
     }
 
-    fn record_error(&mut self, now: Instant) {
-        self.error_records.push_front(now);
+    fn check(&mut self) -> Result<(), SenderError> {
+        for_each_sender(&mut self.senders, |sender| sender.check())
+    }
+}
 
-        if self.error_records.len() > self.max_errors {
-            self.error_records.pop_back();
+/// How a `SamplingSender` decides which data to keep.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampling {
+    /// Keep the first of every `n` emits. Zero and one keep everything.
+    OneIn(u64),
+    /// Keep at most this many emits per second, allowing bursts of up to one second's worth, but at least one emit.
+    PerSecond(f64),
+}
+
+/// A Sender which drops part of the data before it reaches the inner sender, so that noisy logs don't overwhelm fluentd.
+///
+/// Dropped data is not an error, so `emit` returns `Ok` for it. `dropped` counts how much data was
+/// dropped so far. `flush` and `check` are always forwarded.
+///
+/// # Examples
+///
+/// ```
+/// use fluent::sender::{Sampling, SamplingSender, Sender, WriterSender};
+///
+/// let mut sender = SamplingSender::new(WriterSender::new(Vec::new()), Sampling::OneIn(2));
+/// sender.emit(b"foo").unwrap();
+/// sender.emit(b"bar").unwrap();
+/// sender.emit(b"baz").unwrap();
+///
+/// assert_eq!(sender.dropped(), 1);
+/// assert_eq!(sender.into_inner().into_inner(), b"foobaz");
+/// ```
+pub struct SamplingSender<S: Sender, C: Clock = SystemClock> {
+    sender: S,
+    sampling: Sampling,
+    clock: C,
+    seen: u64,
+    dropped: u64,
+    tokens: f64,
+    last_refill: Option<Instant>,
+}
+
+impl<S: Sender> SamplingSender<S> {
+    pub fn new(sender: S, sampling: Sampling) -> SamplingSender<S> {
+        SamplingSender::with_clock(sender, sampling, SystemClock)
+    }
+}
+
+impl<S: Sender, C: Clock> SamplingSender<S, C> {
+    /// Same as `new`, but the rate limit reads the current time from `clock`.
+    pub fn with_clock(sender: S, sampling: Sampling, clock: C) -> SamplingSender<S, C> {
+        let tokens = match sampling {
+            // the bucket holds at least one token, so rates below one per second keep something
+            Sampling::PerSecond(rate) => rate.max(1.0),
+            Sampling::OneIn(_) => 0.0,
+        };
+        SamplingSender {
+            sender,
+            sampling,
+            clock,
+            seen: 0,
+            dropped: 0,
+            tokens,
+            last_refill: None,
         }
     }
 
-    fn should_retry(&self, now: Instant) -> bool {
-        match self.error_records.back() {
-            Some(last) => (now - *last) >= self.wait,
-            None => true,
+    /// Number of emits dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.sender
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sender
+    }
+
+    fn keep(&mut self) -> bool {
+        match self.sampling {
+            Sampling::OneIn(n) => {
+                // u64::is_multiple_of needs Rust 1.87
+                #[allow(clippy::manual_is_multiple_of)]
+                let keep = n <= 1 || self.seen % n == 0;
+                self.seen = self.seen.wrapping_add(1);
+                keep
+            },
+            Sampling::PerSecond(rate) => {
+                let now = self.clock.now();
+                if let Some(last) = self.last_refill {
+                    let elapsed = now.saturating_duration_since(last).as_secs_f64();
+                    self.tokens = (self.tokens + elapsed * rate).min(rate.max(1.0));
+                }
+                self.last_refill = Some(now);
+                if self.tokens >= 1.0 {
+                    self.tokens -= 1.0;
+                    true
+                } else {
+                    false
+                }
+            },
         }
     }
 }
 
-/// Provides feature to handle error (for example, log to local file / raise alert, etc)
-pub trait ErrorHandler {
+impl<S: Sender, C: Clock> Sender for SamplingSender<S, C> {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        if self.keep() {
+            self.sender.emit(data)
+        } else {
+            self.dropped = self.dropped.saturating_add(1);
+            Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), SenderError> {
+        self.sender.flush()
+    }
 
-    fn handle_error(&mut self, timestamp: Instant, error: &SenderError, unsent_data: &[u8]);
+    fn check(&mut self) -> Result<(), SenderError> {
+        self.sender.check()
+    }
 }
 
-/// Do nothing when error occurred.
-pub struct NullHandler;
+/// A Sender implementation writing directly to any `std::io::Write`.
+///
+/// Useful for custom sinks such as pipes or files, and for inspecting emitted data in tests.
+///
+/// # Examples
+///
+/// ```
+/// use fluent::sender::{Sender, WriterSender};
+///
+/// let mut sender = WriterSender::new(Vec::new());
+///
+/// sender.emit("[\"foo.bar\",1500564758,{\"key\":\"value\"}]".as_bytes()).unwrap();
+/// assert_eq!(sender.get_ref().as_slice(), "[\"foo.bar\",1500564758,{\"key\":\"value\"}]".as_bytes());
+/// ```
+pub struct WriterSender<W: Write> {
+    writer: W,
+}
 
-impl ErrorHandler for NullHandler {
+impl<W: Write> WriterSender<W> {
+    pub fn new(writer: W) -> WriterSender<W> {
+        WriterSender { writer }
+    }
 
-    fn handle_error(&mut self, _: Instant, _: &SenderError, _: &[u8]) { /* do nothing */ }
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
 }
 
-/// Provides feature to send bytes to fluentd.
-pub trait Sender {
+impl<W: Write> Sender for WriterSender<W> {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        self.writer.write_all(data).map_err(SenderError::from)
+    }
 
-    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError>;
+    fn flush(&mut self) -> Result<(), SenderError> {
+        self.writer.flush().map_err(SenderError::from)
+    }
 }
 
-pub enum SenderError {
-    IO(IOError),
-    TooLargeData,
+/// A cloneable, thread-safe handle to a Sender.
+///
+/// All clones share one underlying sender behind a mutex, so every `emit` and `flush` takes the lock.
+/// This is simple but serializes logging across threads; under heavy contention prefer one sender per thread.
+///
+/// # Examples
+///
+/// ```
+/// use fluent::sender::{Sender, SharedSender, WriterSender};
+/// use std::thread;
+///
+/// let sender = SharedSender::new(WriterSender::new(Vec::new()));
+///
+/// let mut handle = sender.clone();
+/// thread::spawn(move || {
+///     handle.emit(b"foo").unwrap();
+/// }).join().unwrap();
+///
+/// assert_eq!(sender.into_inner().unwrap().into_inner(), b"foo");
+/// ```
+pub struct SharedSender<S: Sender> {
+    inner: Arc<Mutex<S>>,
 }
 
-/// A Sender implementation via TCP.
+impl<S: Sender> SharedSender<S> {
+    pub fn new(sender: S) -> SharedSender<S> {
+        SharedSender { inner: Arc::new(Mutex::new(sender)) }
+    }
+
+    /// Returns the underlying sender if this is the last handle.
+    pub fn into_inner(self) -> Option<S> {
+        Arc::try_unwrap(self.inner).ok().map(|m| m.into_inner().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl<S: Sender> Clone for SharedSender<S> {
+    fn clone(&self) -> SharedSender<S> {
+        SharedSender { inner: self.inner.clone() }
+    }
+}
+
+impl<S: Sender> Sender for SharedSender<S> {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        self.inner.lock().map_err(|_| SenderError::Poisoned)?.emit(data)
+    }
+
+    fn flush(&mut self) -> Result<(), SenderError> {
+        self.inner.lock().map_err(|_| SenderError::Poisoned)?.flush()
+    }
+
+    fn check(&mut self) -> Result<(), SenderError> {
+        self.inner.lock().map_err(|_| SenderError::Poisoned)?.check()
+    }
+}
+
+enum Message {
+    Emit(Vec<u8>),
+    Flush,
+    Stop,
+}
+
+/// A Sender which hands data to a worker thread, so that application threads never wait for the network.
+///
+/// `emit` only copies the data into a bounded channel, and the worker emits it to the wrapped sender,
+/// which deals with buffering and retries off the hot path. When the worker can't keep up and the
+/// channel holds `capacity` frames, `emit` blocks until there is room again. Get a cloneable handle
+/// with `handle` to emit from several threads. Frames from one thread arrive in the order they were emitted.
+///
+/// Errors of the wrapped sender are not returned to the caller, so handle them with its error handler.
+/// `shutdown` processes every frame sent before it, flushes the wrapped sender and returns it. Dropping
+/// the sender does the same, but discards the wrapped sender.
 ///
 /// # Examples
 ///
 /// ```
-/// use fluent::sender::{ConstantDelay, Sender, TcpSender, NullHandler};
+/// use fluent::sender::{BackgroundSender, Sender, WriterSender};
+/// use std::thread;
 ///
-/// let mut sender = TcpSender::new("127.0.0.1:24224", ConstantDelay::new(), NullHandler).unwrap();
+/// let mut sender = BackgroundSender::new(WriterSender::new(Vec::new()), 1024);
+///
+/// let mut handle = sender.handle();
+/// thread::spawn(move || {
+///     handle.emit(b"foo").unwrap();
+/// }).join().unwrap();
+/// sender.emit(b"bar").unwrap();
+///
+/// assert_eq!(sender.shutdown().unwrap().into_inner(), b"foobar");
+/// ```
+pub struct BackgroundSender<S: Sender + Send + 'static> {
+    handle: BackgroundHandle,
+    worker: Option<thread::JoinHandle<S>>,
+}
+
+impl<S: Sender + Send + 'static> BackgroundSender<S> {
+    /// Starts the worker thread. The channel holds up to `capacity` frames.
+    pub fn new(sender: S, capacity: usize) -> BackgroundSender<S> {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let mut sender = sender;
+        let worker = thread::spawn(move || {
+            for message in rx {
+                match message {
+                    Message::Emit(data) => { let _ = sender.emit(&data); },
+                    Message::Flush => { let _ = sender.flush(); },
+                    Message::Stop => break,
+                }
+            }
+            let _ = sender.flush();
+            sender
+        });
+
+        BackgroundSender {
+            handle: BackgroundHandle { tx },
+            worker: Some(worker),
+        }
+    }
+
+    /// Returns a handle which emits to the same worker, for use from another thread.
+    pub fn handle(&self) -> BackgroundHandle {
+        self.handle.clone()
+    }
+
+    /// Stops the worker after it processed everything sent so far, and returns the wrapped sender.
+    ///
+    /// Handles emitting after this fail with an `IO` error.
+    pub fn shutdown(mut self) -> Result<S, SenderError> {
+        self.stop().ok_or(SenderError::Poisoned)
+    }
+
+    fn stop(&mut self) -> Option<S> {
+        let worker = self.worker.take()?;
+        let _ = self.handle.tx.send(Message::Stop);
+        worker.join().ok()
+    }
+}
+
+impl<S: Sender + Send + 'static> Sender for BackgroundSender<S> {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        self.handle.emit(data)
+    }
+
+    fn flush(&mut self) -> Result<(), SenderError> {
+        self.handle.flush()
+    }
+}
+
+impl<S: Sender + Send + 'static> Drop for BackgroundSender<S> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// A cloneable handle to a `BackgroundSender`.
+#[derive(Clone)]
+pub struct BackgroundHandle {
+    tx: SyncSender<Message>,
+}
+
+impl BackgroundHandle {
+    fn send(&self, message: Message) -> Result<(), SenderError> {
+        self.tx.send(message).map_err(|_| SenderError::IO(IOError::new(ErrorKind::NotConnected, "background sender is shut down")))
+    }
+}
+
+impl Sender for BackgroundHandle {
+    /// Queues the data, blocking while the channel is full.
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        self.send(Message::Emit(data.to_vec()))
+    }
+
+    /// Asks the worker to flush the wrapped sender, without waiting for it.
+    fn flush(&mut self) -> Result<(), SenderError> {
+        self.send(Message::Flush)
+    }
+}
+
+/// A Sender implementation appending each frame and a newline to a local file.
+///
+/// Combined with JSON frames this produces newline-delimited JSON, useful for local archival or replay.
+/// Once the file would grow beyond `max_size` bytes it is rotated: `app.log` becomes `app.log.1`,
+/// `app.log.1` becomes `app.log.2` and so on. `flush` hands buffered data to the OS.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fluent::sender::{FileSender, Sender};
+///
+/// let mut sender = FileSender::new("/var/log/app/fluent.log", 100 * 1024 * 1024).unwrap();
+///
+/// sender.emit("[\"foo.bar\",1500564758,{\"key\":\"value\"}]".as_bytes()).unwrap();
+/// sender.flush().unwrap();
+/// ```
+pub struct FileSender {
+    path: PathBuf,
+    max_size: u64,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl FileSender {
+    /// Opens `path` for appending. Data already in the file counts toward `max_size`.
+    pub fn new<P: AsRef<Path>>(path: P, max_size: u64) -> Result<FileSender, IOError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(FileSender {
+            path,
+            max_size,
+            file: BufWriter::new(file),
+            size,
+        })
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> Result<(), IOError> {
+        self.file.flush()?;
+
+        let mut last = 1;
+        while self.rotated_path(last).exists() {
+            last += 1;
+        }
+        for n in (1..last).rev() {
+            fs::rename(self.rotated_path(n), self.rotated_path(n + 1))?;
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+
+        self.file = BufWriter::new(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Sender for FileSender {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        let len = data.len() as u64 + 1;
+
+        // a frame larger than max_size still gets a file of its own
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(data)?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SenderError> {
+        self.file.flush().map_err(SenderError::IO)
+    }
+}
+
+/// A Sender implementation via UDP.
+///
+/// Each `emit` is sent as a single datagram, so nothing is buffered and nothing is retried.
+/// Data larger than the MTU (65507 bytes by default) is rejected with `TooLargeData`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fluent::sender::{Sender, UdpSender, NullHandler};
+///
+/// let mut sender = UdpSender::new("127.0.0.1:24224", NullHandler).unwrap();
 ///
 /// sender.emit("[\"foo.bar\",1500564758,{\"key\":\"value\"}]".as_bytes());
 /// ```
-pub struct TcpSender<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler> {
-    addr: A,
-    stream: TcpStream,
+pub struct UdpSender<H: ErrorHandler> {
+    socket: UdpSocket,
+    error_handler: H,
+    mtu: usize,
+}
+
+impl<H: ErrorHandler> UdpSender<H> {
+    pub fn new<A: ToSocketAddrs>(addr: A, error_handler: H) -> Result<UdpSender<H>, IOError> {
+        UdpSender::with_mtu(addr, 65507, error_handler)
+    }
+
+    pub fn with_mtu<A: ToSocketAddrs>(addr: A, mtu: usize, error_handler: H) -> Result<UdpSender<H>, IOError> {
+        let local = match addr.to_socket_addrs()?.next() {
+            Some(ref a) if a.is_ipv6() => "[::]:0",
+            _ => "0.0.0.0:0",
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+
+        Ok(UdpSender {
+            socket,
+            error_handler,
+            mtu,
+        })
+    }
+}
+
+impl<H: ErrorHandler> Sender for UdpSender<H> {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        if data.len() > self.mtu {
+            Err(SenderError::TooLargeData { data_len: data.len(), capacity: self.mtu }) ?
+        }
+
+        match self.socket.send(data) {
+            Err(e) => {
+                let err = SenderError::IO(e);
+                // nothing is buffered, so there is nothing to retain or drop
+                self.error_handler.handle_error(Instant::now(), &err, data);
+                Err(err)
+            },
+            Ok(_) => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), SenderError> {
+        // datagrams are sent immediately, nothing to flush
+        Ok(())
+    }
+}
+
+/// A Sender implementation via Unix domain socket.
+///
+/// Buffering and reconnection behave the same as `TcpSender`. This works with fluentd's `unix` input,
+/// which speaks the forward protocol over a socket file:
+///
+/// ```text
+/// <source>
+///   @type unix
+///   path /var/run/fluent/fluent.sock
+/// </source>
+/// ```
+///
+/// fluentd can't restrict clients by credentials, so access is controlled by the permissions of the
+/// socket file. `local_addr` and `peer_addr` show which socket the sender is connected through.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fluent::sender::{ConstantDelay, Sender, UnixSocketSender, NullHandler};
+///
+/// let mut sender = UnixSocketSender::new("/var/run/fluent/fluent.sock", ConstantDelay::new(), NullHandler).unwrap();
+///
+/// sender.emit("[\"foo.bar\",1500564758,{\"key\":\"value\"}]".as_bytes());
+/// ```
+#[cfg(unix)]
+pub struct UnixSocketSender<R: RetryManager, H: ErrorHandler> {
+    path: PathBuf,
+    stream: UnixStream,
     retry_manager: R,
     error_handler: H,
-    buffer: Vec<u8>,
+    buffer: FrameBuffer,
 }
 
-impl<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler> TcpSender<A, R, H> {
-    pub fn new(addr: A, retry_manager: R, error_handler: H) -> Result<TcpSender<A, R, H>, IOError> {
-        TcpStream::connect(addr).map(|stream| {
-            TcpSender {
-                addr: addr,
-                stream: stream,
-                retry_manager: retry_manager,
-                buffer: Vec::with_capacity(8 * 1024 * 1024), // 8MB
-                error_handler: error_handler,
+#[cfg(unix)]
+impl<R: RetryManager, H: ErrorHandler> UnixSocketSender<R, H> {
+    pub fn new<P: AsRef<Path>>(path: P, retry_manager: R, error_handler: H) -> Result<UnixSocketSender<R, H>, IOError> {
+        let path = path.as_ref().to_path_buf();
+        UnixStream::connect(&path).map(|stream| {
+            UnixSocketSender {
+                path,
+                stream,
+                retry_manager,
+                buffer: FrameBuffer::new(8 * 1024 * 1024, OverflowPolicy::RejectNew), // 8MB
+                error_handler,
             }
         })
     }
 
+    /// Address of the local end of the current connection, usually unnamed.
+    pub fn local_addr(&self) -> Result<UnixSocketAddr, IOError> {
+        self.stream.local_addr()
+    }
+
+    /// Address of the socket the sender is currently connected to.
+    pub fn peer_addr(&self) -> Result<UnixSocketAddr, IOError> {
+        self.stream.peer_addr()
+    }
+
+    /// Returns false if the peer closed the connection or the socket has a pending error.
+    fn connection_alive(&self) -> bool {
+        match self.stream.take_error() {
+            Ok(None) => (),
+            _ => return false,
+        }
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+
+        // UnixStream::peek is unstable, so read instead. fluentd only writes to the socket to
+        // answer ack requests, which this sender never makes, so there is nothing to lose.
+        let mut buf = [0; 1];
+        let alive = match (&self.stream).read(&mut buf) {
+            // closed by peer
+            Ok(0) => false,
+            Ok(_) => true,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => true,
+            Err(_) => false,
+        };
+        self.stream.set_nonblocking(false).is_ok() && alive
+    }
+
     fn send_buffer_with_reconnect_once(&mut self) -> Result<(), IOError> {
-        match self.stream.write(self.buffer.as_slice()) {
+        match self.stream.write_all(self.buffer.as_slice()) {
             Err(_) => {
-                TcpStream::connect(self.addr).and_then(|new_stream| {
+                UnixStream::connect(&self.path).and_then(|new_stream| {
                     self.stream = new_stream;
-                    self.stream.write(self.buffer.as_slice()).map(|_| ())
+                    self.stream.write_all(self.buffer.as_slice())
                 })
             },
             Ok(_) => Ok(()),
         }
     }
+}
+
+#[cfg(unix)]
+impl<R: RetryManager, H: ErrorHandler> BufferedSender for UnixSocketSender<R, H> {
+    fn frame_buffer(&mut self) -> &mut FrameBuffer {
+        &mut self.buffer
+    }
+
+    fn should_retry(&self, now: Instant) -> bool {
+        self.retry_manager.should_retry(now)
+    }
+
+    fn handle_error(&mut self, now: Instant, err: &SenderError) {
+        match self.error_handler.handle_error(now, err, self.buffer.as_slice()) {
+            ErrorAction::Retain => (),
+            ErrorAction::Drop => self.buffer.clear(),
+            ErrorAction::Reset => self.retry_manager.clear_errors(),
+        }
+    }
 
     fn flush_buffer(&mut self) -> Result<(), SenderError> {
         if self.buffer.is_empty() {
@@ -152,7 +2416,7 @@ impl<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler> TcpSender<A, R,
                     let now = Instant::now();
                     let err = SenderError::IO(e);
                     self.retry_manager.record_error(now);
-                    self.error_handler.handle_error(now, &err, self.buffer.as_slice());
+                    self.handle_error(now, &err);
                     Err(err)
                 },
                 Ok(_) => {
@@ -165,26 +2429,72 @@ impl<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler> TcpSender<A, R,
     }
 }
 
-impl<A: ToSocketAddrs + Copy, R: RetryManager, H: ErrorHandler> Sender for TcpSender<A, R, H> {
+#[cfg(unix)]
+impl<R: RetryManager, H: ErrorHandler> Sender for UnixSocketSender<R, H> {
     fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        self.buffer_frames(Instant::now(), &[data])
+    }
 
-        let now = Instant::now();
+    fn flush(&mut self) -> Result<(), SenderError> {
+        self.flush_buffer()
+    }
 
-        // if buffer space is insufficient, flush first
-        if self.buffer.len() + data.len() > self.buffer.capacity() && self.retry_manager.should_retry(now) {
-            self.flush_buffer() ?
+    fn check(&mut self) -> Result<(), SenderError> {
+        if self.connection_alive() {
+            return Ok(());
         }
-        // if data is larger than buffer capacity, just return error.
-        if data.len() > self.buffer.capacity() - self.buffer.len() {
-            Err(SenderError::TooLargeData) ?
+        let stream = UnixStream::connect(&self.path)?;
+        self.stream = stream;
+        Ok(())
+    }
+}
+
+/// Flushes buffered data on a best-effort basis. Errors are passed to the error handler and otherwise ignored.
+#[cfg(unix)]
+impl<R: RetryManager, H: ErrorHandler> Drop for UnixSocketSender<R, H> {
+    fn drop(&mut self) {
+        let _ = self.flush_buffer();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameBuffer, OverflowPolicy};
+    use std::io::{Error as IOError, ErrorKind, Write};
+
+    /// Accepts at most `max` bytes per write, and fails the second write with `Interrupted`.
+    struct ShortWriter {
+        data: Vec<u8>,
+        max: usize,
+        writes: usize,
+    }
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, IOError> {
+            self.writes += 1;
+            if self.writes == 2 {
+                return Err(IOError::new(ErrorKind::Interrupted, "interrupted"));
+            }
+            let n = buf.len().min(self.max);
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
         }
 
-        // write to buffer then flush
-        self.buffer.extend_from_slice(data);
-        if self.retry_manager.should_retry(now) {
-            self.flush_buffer()
-        } else {
+        fn flush(&mut self) -> Result<(), IOError> {
             Ok(())
         }
     }
+
+    #[test]
+    fn frame_buffer_writes_whole_buffer_through_short_writes() {
+        let mut buffer = FrameBuffer::new(1024, OverflowPolicy::RejectNew);
+        buffer.push(b"foo");
+        buffer.push(b"barbaz");
+
+        let mut writer = ShortWriter { data: Vec::new(), max: 2, writes: 0 };
+        buffer.write_to(&mut writer).unwrap();
+        assert_eq!(writer.data, b"foobarbaz");
+        // five short writes, plus the interrupted one
+        assert_eq!(writer.writes, 6);
+    }
 }
@@ -0,0 +1,75 @@
+//! Helpers for testing code which uses senders. Enabled by the `testing` feature.
+
+use std::collections::VecDeque;
+
+use sender::{Sender, SenderError};
+
+/// A Sender which records emitted data and returns scripted results.
+///
+/// Each call to `emit` or `flush` takes the next queued result for that method, and succeeds once
+/// the queue is empty. Only data whose `emit` succeeded is recorded.
+///
+/// # Examples
+///
+/// ```
+/// use fluent::sender::{Sender, SenderError};
+/// use fluent::testing::MockSender;
+///
+/// let mut sender = MockSender::new();
+/// sender.push_emit_result(Err(SenderError::BufferFull));
+///
+/// assert!(sender.emit(b"foo").is_err());
+/// assert!(sender.emit(b"bar").is_ok());
+/// assert!(sender.flush().is_ok());
+///
+/// assert_eq!(sender.emitted(), &[b"bar".to_vec()]);
+/// assert_eq!(sender.flushes(), 1);
+/// ```
+#[derive(Debug, Default)]
+pub struct MockSender {
+    emitted: Vec<Vec<u8>>,
+    flushes: usize,
+    emit_results: VecDeque<Result<(), SenderError>>,
+    flush_results: VecDeque<Result<(), SenderError>>,
+}
+
+impl MockSender {
+    pub fn new() -> MockSender {
+        MockSender::default()
+    }
+
+    /// Queues the result of a future `emit` call.
+    pub fn push_emit_result(&mut self, result: Result<(), SenderError>) {
+        self.emit_results.push_back(result);
+    }
+
+    /// Queues the result of a future `flush` call.
+    pub fn push_flush_result(&mut self, result: Result<(), SenderError>) {
+        self.flush_results.push_back(result);
+    }
+
+    /// Data of each successful `emit`, oldest first.
+    pub fn emitted(&self) -> &[Vec<u8>] {
+        &self.emitted
+    }
+
+    /// Number of `flush` calls, including failed ones.
+    pub fn flushes(&self) -> usize {
+        self.flushes
+    }
+}
+
+impl Sender for MockSender {
+    fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+        let result = self.emit_results.pop_front().unwrap_or(Ok(()));
+        if result.is_ok() {
+            self.emitted.push(data.to_vec());
+        }
+        result
+    }
+
+    fn flush(&mut self) -> Result<(), SenderError> {
+        self.flushes += 1;
+        self.flush_results.pop_front().unwrap_or(Ok(()))
+    }
+}
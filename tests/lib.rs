@@ -1 +1,1668 @@
 extern crate fluent;
+
+use fluent::sender::{Clock, NullHandler, RetryManager, Sender, SenderError, UdpSender};
+use std::cell::Cell;
+use std::net::UdpSocket;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Never allows flushing from emit, so data stays in the buffer.
+struct AlwaysWait;
+
+impl RetryManager for AlwaysWait {
+    fn clear_errors(&mut self) {}
+    fn record_error(&mut self, _: Instant) {}
+    fn should_retry(&self, _: Instant) -> bool { false }
+}
+
+/// A clock which only moves when the test sets it.
+#[derive(Clone)]
+struct FakeClock(Rc<Cell<Instant>>);
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant { self.0.get() }
+}
+
+#[test]
+fn udp_sender_emits_one_datagram_per_call() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut sender = UdpSender::new(server.local_addr().unwrap(), NullHandler).unwrap();
+
+    assert!(sender.emit(b"foo").is_ok());
+    assert!(sender.emit(b"barbaz").is_ok());
+    assert!(sender.flush().is_ok());
+
+    let mut buf = [0; 16];
+    let n = server.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"foo");
+    let n = server.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"barbaz");
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_sender_exposes_socket_addresses() {
+    use fluent::sender::{ConstantDelay, UnixSocketSender};
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("fluent-test-addr-{}.sock", process::id()));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let mut sender = UnixSocketSender::new(&path, ConstantDelay::new(), NullHandler).unwrap();
+    assert_eq!(sender.peer_addr().unwrap().as_pathname(), Some(path.as_path()));
+    assert!(sender.local_addr().unwrap().is_unnamed());
+    assert!(sender.emit(b"foo").is_ok());
+    drop(sender);
+
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"foo");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn udp_sender_rejects_data_larger_than_mtu() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut sender = UdpSender::with_mtu(server.local_addr().unwrap(), 4, NullHandler).unwrap();
+
+    match sender.emit(b"12345") {
+        Err(SenderError::TooLargeData { data_len: 5, capacity: 4 }) => (),
+        _ => panic!("expected TooLargeData"),
+    }
+    assert!(sender.emit(b"1234").is_ok());
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_sender_emits_to_listener() {
+    use fluent::sender::{ConstantDelay, UnixSocketSender};
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("fluent-test-{}.sock", process::id()));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let mut sender = UnixSocketSender::new(&path, ConstantDelay::new(), NullHandler).unwrap();
+    assert!(sender.emit(b"foo").is_ok());
+    assert!(sender.emit(b"bar").is_ok());
+    drop(sender);
+
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"foobar");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn tcp_sender_sends_whole_buffer() {
+    use fluent::sender::{ConstantDelay, TcpSender};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        received
+    });
+
+    // large enough that a single write() on the socket is typically short
+    let data: Vec<u8> = (0..4 * 1024 * 1024).map(|i| i as u8).collect();
+
+    let mut sender = TcpSender::new(addr, ConstantDelay::new(), NullHandler).unwrap();
+    assert!(sender.emit(&data).is_ok());
+    drop(sender);
+
+    assert!(server.join().unwrap() == data);
+}
+
+#[test]
+fn constant_delay_with_params_uses_given_wait() {
+    use fluent::sender::{ConstantDelay, RetryManager};
+    use std::time::{Duration, Instant};
+
+    let mut manager = ConstantDelay::with_params(10, Duration::from_secs(1));
+    let now = Instant::now();
+    manager.record_error(now);
+
+    assert!(!manager.should_retry(now + Duration::from_millis(500)));
+    assert!(manager.should_retry(now + Duration::from_secs(1)));
+}
+
+#[test]
+fn exponential_backoff_is_capped_at_max() {
+    use fluent::sender::{ExponentialBackoff, RetryManager};
+    use std::time::{Duration, Instant};
+
+    let mut manager = ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(1), false);
+    let now = Instant::now();
+    for _ in 0..64 {
+        manager.record_error(now);
+    }
+
+    assert!(!manager.should_retry(now + Duration::from_millis(999)));
+    assert!(manager.should_retry(now + Duration::from_secs(1)));
+}
+
+#[test]
+fn exponential_backoff_jitter_stays_within_backoff() {
+    use fluent::sender::{ExponentialBackoff, RetryManager};
+    use std::time::{Duration, Instant};
+
+    let now = Instant::now();
+    for _ in 0..100 {
+        let mut manager = ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(1), true);
+        manager.record_error(now);
+        manager.record_error(now);
+        assert!(manager.should_retry(now + Duration::from_millis(200)));
+    }
+}
+
+#[test]
+fn sender_error_is_std_error() {
+    use std::error::Error;
+    use std::io;
+
+    let err: Box<dyn Error> = Box::new(SenderError::IO(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed")));
+    assert_eq!(err.to_string(), "failed to send data: pipe closed");
+    assert_eq!(err.source().unwrap().to_string(), "pipe closed");
+
+    let err = SenderError::TooLargeData { data_len: 10, capacity: 8 };
+    assert_eq!(err.to_string(), "data of 10 bytes is larger than the buffer capacity of 8 bytes");
+    assert!(err.source().is_none());
+}
+
+#[test]
+fn sender_error_is_send_and_sync() {
+    use std::error::Error;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+    assert_send_sync::<SenderError>();
+
+    let err = thread::spawn(|| -> Box<dyn Error + Send + Sync> { Box::new(SenderError::BufferFull) }).join().unwrap();
+    assert_eq!(err.to_string(), "buffer is full of unsent data");
+}
+
+#[test]
+fn io_errors_convert_into_sender_error_with_question_mark() {
+    use fluent::sender::WriterSender;
+    use std::io::{self, Read};
+
+    fn forward<R: Read, S: Sender>(mut source: R, sender: &mut S) -> Result<(), SenderError> {
+        let mut data = Vec::new();
+        source.read_to_end(&mut data)?;
+        sender.emit(&data)?;
+        sender.flush()
+    }
+
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken"))
+        }
+    }
+
+    let mut sender = WriterSender::new(Vec::new());
+    assert!(forward(&b"foo"[..], &mut sender).is_ok());
+    assert_eq!(sender.get_ref(), b"foo");
+    match forward(Broken, &mut sender) {
+        Err(SenderError::IO(ref e)) if e.to_string() == "broken" => (),
+        _ => panic!("expected IO error"),
+    }
+}
+
+#[test]
+fn emit_blocking_retries_full_buffer() {
+    use std::time::Duration;
+
+    // rejects the first `full` emits as if its buffer were full
+    struct Busy {
+        full: usize,
+        emitted: Vec<u8>,
+    }
+
+    impl Sender for Busy {
+        fn emit(&mut self, data: &[u8]) -> Result<(), SenderError> {
+            if data.len() > 3 {
+                Err(SenderError::TooLargeData { data_len: data.len(), capacity: 3 }) ?
+            }
+            if self.full > 0 {
+                self.full -= 1;
+                Err(SenderError::BufferFull) ?
+            }
+            self.emitted.extend_from_slice(data);
+            Ok(())
+        }
+        fn flush(&mut self) -> Result<(), SenderError> { Ok(()) }
+    }
+
+    let delay = Duration::from_millis(1);
+    let mut sender = Busy { full: 1, emitted: Vec::new() };
+    assert!(sender.emit_blocking(b"foo", 2, delay).is_ok());
+    assert_eq!(sender.emitted, b"foo");
+
+    // gives up after the given number of attempts
+    sender.full = 3;
+    match sender.emit_blocking(b"bar", 3, delay) {
+        Err(SenderError::BufferFull) => (),
+        _ => panic!("expected BufferFull"),
+    }
+    assert_eq!(sender.full, 0);
+
+    // too large data is not retried
+    sender.full = 1;
+    match sender.emit_blocking(b"quux", 3, delay) {
+        Err(SenderError::TooLargeData { .. }) => (),
+        _ => panic!("expected TooLargeData"),
+    }
+    assert_eq!(sender.full, 1);
+}
+
+#[test]
+fn tcp_sender_flushes_on_drop() {
+    use fluent::sender::TcpSender;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSender::new(listener.local_addr().unwrap(), AlwaysWait, NullHandler).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+
+    assert!(sender.emit(b"foo").is_ok());
+    assert!(sender.emit(b"bar").is_ok());
+    drop(sender);
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"foobar");
+}
+
+#[test]
+fn tcp_sender_builder_applies_buffer_capacity() {
+    use fluent::sender::{ConstantDelay, TcpSenderBuilder};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .connect_timeout(Duration::from_secs(1))
+        .write_timeout(Duration::from_secs(1))
+        .nodelay(true)
+        .buffer_capacity(4)
+        .build(listener.local_addr().unwrap(), ConstantDelay::new(), NullHandler)
+        .unwrap();
+
+    assert!(sender.emit(b"1234").is_ok());
+    match sender.emit(b"12345") {
+        Err(SenderError::TooLargeData { .. }) => (),
+        _ => panic!("expected TooLargeData"),
+    }
+}
+
+#[test]
+fn shared_sender_returns_error_when_poisoned() {
+    use fluent::sender::SharedSender;
+    use std::thread;
+
+    struct PanickingSender;
+
+    impl Sender for PanickingSender {
+        fn emit(&mut self, _: &[u8]) -> Result<(), SenderError> { panic!("boom") }
+        fn flush(&mut self) -> Result<(), SenderError> { Ok(()) }
+    }
+
+    let mut sender = SharedSender::new(PanickingSender);
+    let mut handle = sender.clone();
+    assert!(thread::spawn(move || handle.emit(b"foo")).join().is_err());
+
+    match sender.emit(b"foo") {
+        Err(SenderError::Poisoned) => (),
+        _ => panic!("expected Poisoned"),
+    }
+}
+
+#[test]
+fn file_spool_handler_replays_spooled_data() {
+    use fluent::sender::{ErrorHandler, FileSpoolHandler, WriterSender};
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::process;
+    use std::time::Instant;
+
+    let path = env::temp_dir().join(format!("fluent-test-{}-replay.spool", process::id()));
+    let _ = fs::remove_file(&path);
+    let err = SenderError::IO(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"));
+
+    let mut handler = FileSpoolHandler::new(&path, 1024);
+    handler.handle_error(Instant::now(), &err, b"foo");
+    handler.handle_error(Instant::now(), &err, b"bar");
+
+    // a new handler sees what the previous one spooled
+    let mut sender = WriterSender::new(Vec::new());
+    assert_eq!(FileSpoolHandler::new(&path, 1024).replay(&mut sender).unwrap(), 2);
+    assert_eq!(sender.get_ref().as_slice(), b"foobar");
+
+    // spool is empty after replay
+    assert_eq!(handler.replay(&mut sender).unwrap(), 0);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_spool_handler_replay_does_not_spool_failed_record_twice() {
+    use fluent::sender::{ConstantDelay, ErrorHandler, FileSpoolHandler, TcpSender, WriterSender};
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::net::{Shutdown, TcpListener};
+    use std::process;
+    use std::time::Instant;
+
+    let path = env::temp_dir().join(format!("fluent-test-{}-replay-dead.spool", process::id()));
+    let _ = fs::remove_file(&path);
+    let err = SenderError::IO(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"));
+
+    let mut handler = FileSpoolHandler::new(&path, 1024);
+    handler.handle_error(Instant::now(), &err, b"foo");
+    handler.handle_error(Instant::now(), &err, b"bar");
+
+    // writes fail and reconnecting is refused, so the sender spools what it buffered
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSender::new(listener.local_addr().unwrap(), ConstantDelay::new(), FileSpoolHandler::new(&path, 1024)).unwrap();
+    sender.stream().shutdown(Shutdown::Write).unwrap();
+    drop(listener);
+
+    assert!(handler.replay(&mut sender).is_err());
+    drop(sender);
+
+    let mut spooled = WriterSender::new(Vec::new());
+    assert_eq!(handler.replay(&mut spooled).unwrap(), 2);
+    assert_eq!(spooled.get_ref().as_slice(), b"foobar");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_spool_handler_evicts_oldest_records() {
+    use fluent::sender::{ErrorHandler, FileSpoolHandler, WriterSender};
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::process;
+    use std::time::Instant;
+
+    let path = env::temp_dir().join(format!("fluent-test-{}-evict.spool", process::id()));
+    let _ = fs::remove_file(&path);
+    let err = SenderError::IO(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"));
+
+    // each record takes 4 bytes of length header plus 3 bytes of data
+    let mut handler = FileSpoolHandler::new(&path, 14);
+    handler.handle_error(Instant::now(), &err, b"foo");
+    handler.handle_error(Instant::now(), &err, b"bar");
+    handler.handle_error(Instant::now(), &err, b"baz");
+
+    let mut sender = WriterSender::new(Vec::new());
+    assert_eq!(handler.replay(&mut sender).unwrap(), 2);
+    assert_eq!(sender.get_ref().as_slice(), b"barbaz");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_spool_handler_replay_keeps_records_until_flushed() {
+    use fluent::sender::{ErrorHandler, FileSpoolHandler, WriterSender};
+    use std::env;
+    use std::fs;
+    use std::io::{self, Write};
+    use std::process;
+    use std::time::Instant;
+
+    struct FailingFlush(Vec<u8>);
+
+    impl Write for FailingFlush {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.write(buf) }
+        fn flush(&mut self) -> io::Result<()> { Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed")) }
+    }
+
+    let path = env::temp_dir().join(format!("fluent-test-{}-replay-flush.spool", process::id()));
+    let _ = fs::remove_file(&path);
+    let err = SenderError::IO(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"));
+
+    let mut handler = FileSpoolHandler::new(&path, 1024);
+    handler.handle_error(Instant::now(), &err, b"foo");
+    handler.handle_error(Instant::now(), &err, b"bar");
+
+    // written, but never flushed
+    assert!(handler.replay(&mut WriterSender::new(FailingFlush(Vec::new()))).is_err());
+
+    let mut sender = WriterSender::new(Vec::new());
+    assert_eq!(handler.replay(&mut sender).unwrap(), 2);
+    assert_eq!(sender.get_ref().as_slice(), b"foobar");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_spool_handler_retains_data_larger_than_spool() {
+    use fluent::sender::{ErrorAction, ErrorHandler, FileSpoolHandler, WriterSender};
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::process;
+    use std::time::Instant;
+
+    let path = env::temp_dir().join(format!("fluent-test-{}-oversized.spool", process::id()));
+    let _ = fs::remove_file(&path);
+    let err = SenderError::IO(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"));
+
+    let mut handler = FileSpoolHandler::new(&path, 10);
+    handler.handle_error(Instant::now(), &err, b"foo");
+    // the sender keeps data which can't be spooled, and the spool keeps what it had
+    assert_eq!(handler.handle_error(Instant::now(), &err, b"0123456789abcdef"), ErrorAction::Retain);
+
+    let mut sender = WriterSender::new(Vec::new());
+    assert_eq!(handler.replay(&mut sender).unwrap(), 1);
+    assert_eq!(sender.get_ref().as_slice(), b"foo");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn failover_sender_uses_next_endpoint_when_primary_is_down() {
+    use fluent::sender::{ConstantDelay, FailoverSender};
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    // nothing listens on the primary address once the listener is dropped
+    let primary = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let secondary = TcpListener::bind("127.0.0.1:0").unwrap();
+
+    let mut sender = FailoverSender::new(&[primary, secondary.local_addr().unwrap()], ConstantDelay::new(), NullHandler).unwrap();
+    assert!(sender.emit(b"foo").is_ok());
+    drop(sender);
+
+    let (mut stream, _) = secondary.accept().unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"foo");
+}
+
+#[test]
+fn failover_sender_fails_back_to_primary() {
+    use fluent::sender::{ConstantDelay, TcpSenderBuilder};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    let primary = TcpListener::bind("127.0.0.1:0").unwrap();
+    let primary_addr = primary.local_addr().unwrap();
+    drop(primary);
+    let secondary = TcpListener::bind("127.0.0.1:0").unwrap();
+
+    let mut sender = TcpSenderBuilder::new()
+        .connect_timeout(Duration::from_secs(1))
+        .build_failover(&[primary_addr, secondary.local_addr().unwrap()], ConstantDelay::with_params(100, Duration::from_millis(10)), NullHandler)
+        .unwrap();
+    assert!(sender.emit(b"foo").is_ok());
+
+    // once its retry manager allows it, the primary is probed again
+    let primary = TcpListener::bind(primary_addr).unwrap();
+    thread::sleep(Duration::from_millis(20));
+    assert!(sender.emit(b"bar").is_ok());
+    drop(sender);
+
+    let mut received = Vec::new();
+    primary.accept().unwrap().0.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"bar");
+
+    received.clear();
+    secondary.accept().unwrap().0.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"foo");
+}
+
+#[test]
+fn round_robin_sender_rotates_targets() {
+    use fluent::sender::{RoundRobinSender, WriterSender};
+
+    let mut sender = RoundRobinSender::new(vec![
+        WriterSender::new(Vec::new()),
+        WriterSender::new(Vec::new()),
+        WriterSender::new(Vec::new()),
+    ]);
+    for data in &[b"a", b"b", b"c", b"d", b"e"] {
+        assert!(sender.emit(*data).is_ok());
+    }
+    assert!(sender.flush().is_ok());
+
+    let received: Vec<Vec<u8>> = sender.into_inner().into_iter().map(|s| s.into_inner()).collect();
+    assert_eq!(received, vec![b"ad".to_vec(), b"be".to_vec(), b"c".to_vec()]);
+}
+
+#[test]
+fn tcp_sender_drops_buffer_when_handler_says_so() {
+    use fluent::sender::{ErrorAction, ErrorHandler, TcpSenderBuilder};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    struct DropHandler(Vec<u8>);
+
+    impl ErrorHandler for DropHandler {
+        fn handle_error(&mut self, _: Instant, _: &SenderError, unsent_data: &[u8]) -> ErrorAction {
+            self.0.extend_from_slice(unsent_data);
+            ErrorAction::Drop
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .buffer_capacity(4)
+        .build(listener.local_addr().unwrap(), AlwaysWait, DropHandler(Vec::new()))
+        .unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+
+    assert!(sender.emit(b"12").is_ok());
+    assert!(sender.emit(b"34").is_ok());
+    // buffer is full, so "1234" is handed to the handler and dropped
+    assert!(sender.emit(b"56").is_ok());
+    drop(sender);
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"56");
+}
+
+#[test]
+fn tcp_sender_rejects_data_when_handler_retains_full_buffer() {
+    use fluent::sender::TcpSenderBuilder;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .buffer_capacity(4)
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+
+    assert!(sender.emit(b"1234").is_ok());
+    match sender.emit(b"56") {
+        Err(SenderError::BufferFull) => (),
+        _ => panic!("expected BufferFull"),
+    }
+}
+
+#[test]
+fn tcp_sender_distinguishes_full_buffer_from_too_large_data() {
+    use fluent::sender::TcpSenderBuilder;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .buffer_capacity(4)
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+
+    // a record larger than the whole capacity never fits, even into an empty buffer
+    match sender.emit(b"12345") {
+        Err(SenderError::TooLargeData { data_len: 5, capacity: 4 }) => (),
+        _ => panic!("expected TooLargeData"),
+    }
+    assert!(sender.emit(b"123").is_ok());
+    // this one would fit once the buffer is flushed
+    match sender.emit(b"45") {
+        Err(SenderError::BufferFull) => (),
+        _ => panic!("expected BufferFull"),
+    }
+    match sender.emit(b"12345") {
+        Err(SenderError::TooLargeData { data_len: 5, capacity: 4 }) => (),
+        _ => panic!("expected TooLargeData"),
+    }
+}
+
+#[test]
+fn tcp_sender_drop_oldest_evicts_frames() {
+    use fluent::sender::{OverflowPolicy, TcpSenderBuilder};
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .buffer_capacity(6)
+        .overflow_policy(OverflowPolicy::DropOldest)
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+
+    assert!(sender.emit(b"12").is_ok());
+    assert!(sender.emit(b"34").is_ok());
+    assert!(sender.emit(b"56").is_ok());
+    // "12" is evicted to make room
+    assert!(sender.emit(b"78").is_ok());
+    // "34" and "56" are evicted to make room
+    assert!(sender.emit(b"abc").is_ok());
+    drop(sender);
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"78abc");
+}
+
+#[test]
+fn tcp_sender_drop_oldest_evicts_frames_while_endpoint_is_down() {
+    use fluent::sender::{ConstantDelay, OverflowPolicy, TcpSenderBuilder};
+    use std::net::{Shutdown, TcpListener};
+    use std::time::Duration;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .buffer_capacity(6)
+        .overflow_policy(OverflowPolicy::DropOldest)
+        .build(listener.local_addr().unwrap(), ConstantDelay::with_params(100, Duration::from_millis(0)), NullHandler)
+        .unwrap();
+    // every flush fails, and reconnecting is refused
+    sender.stream().shutdown(Shutdown::Write).unwrap();
+    drop(listener);
+
+    // each emit buffers the frame, then reports the failed flush
+    assert!(sender.emit(b"12").is_err());
+    assert!(sender.emit(b"34").is_err());
+    assert!(sender.emit(b"56").is_err());
+    // "12" is evicted even though the flush to make room failed
+    assert!(sender.emit(b"78").is_err());
+
+    let pending: Vec<&[u8]> = sender.pending_frames().collect();
+    assert_eq!(pending, vec![&b"34"[..], &b"56"[..], &b"78"[..]]);
+}
+
+#[test]
+fn tcp_sender_with_capacity_rejects_zero() {
+    use fluent::sender::{ConstantDelay, TcpSender};
+    use std::io::ErrorKind;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    match TcpSender::with_capacity(addr, 0, ConstantDelay::new(), NullHandler) {
+        Err(ref e) if e.kind() == ErrorKind::InvalidInput => (),
+        _ => panic!("expected InvalidInput"),
+    }
+
+    let mut sender = TcpSender::with_capacity(addr, 3, ConstantDelay::new(), NullHandler).unwrap();
+    assert!(sender.emit(b"123").is_ok());
+    match sender.emit(b"1234") {
+        Err(SenderError::TooLargeData { data_len: 4, capacity: 3 }) => (),
+        _ => panic!("expected TooLargeData"),
+    }
+}
+
+#[test]
+fn tcp_sender_never_buffers_past_its_limit() {
+    use fluent::sender::TcpSenderBuilder;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .buffer_capacity(5)
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+
+    for _ in 0..10 {
+        let _ = sender.emit(b"123");
+        let _ = sender.emit(b"45");
+        let _ = sender.emit(b"6");
+    }
+    drop(sender);
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"12345");
+}
+
+#[test]
+fn file_sender_rotates_by_size() {
+    use fluent::sender::FileSender;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    let dir = env::temp_dir().join(format!("fluent-test-{}-rotate", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+    let path = dir.join("app.log");
+
+    let mut sender = FileSender::new(&path, 9).unwrap();
+    assert!(sender.emit(b"1234").is_ok());
+    assert!(sender.emit(b"5678").is_ok());
+    assert!(sender.emit(b"abcd").is_ok());
+    assert!(sender.flush().is_ok());
+
+    assert_eq!(fs::read(dir.join("app.log.2")).unwrap(), b"1234\n");
+    assert_eq!(fs::read(dir.join("app.log.1")).unwrap(), b"5678\n");
+    assert_eq!(fs::read(&path).unwrap(), b"abcd\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn tee_sender_sends_to_every_sender() {
+    use fluent::sender::{TeeSender, WriterSender};
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.borrow_mut().write(buf) }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    struct FailingSender;
+
+    impl Sender for FailingSender {
+        fn emit(&mut self, _: &[u8]) -> Result<(), SenderError> { Err(SenderError::TooLargeData { data_len: 3, capacity: 0 }) }
+        fn flush(&mut self) -> Result<(), SenderError> { Ok(()) }
+    }
+
+    let first = SharedBuf(Rc::new(RefCell::new(Vec::new())));
+    let second = SharedBuf(Rc::new(RefCell::new(Vec::new())));
+    let mut sender = TeeSender::new(vec![
+        Box::new(WriterSender::new(first.clone())),
+        Box::new(FailingSender),
+        Box::new(WriterSender::new(second.clone())),
+    ]);
+
+    match sender.emit(b"foo") {
+        Err(SenderError::TooLargeData { .. }) => (),
+        _ => panic!("expected TooLargeData"),
+    }
+    assert!(sender.flush().is_ok());
+
+    assert_eq!(first.0.borrow().as_slice(), b"foo");
+    assert_eq!(second.0.borrow().as_slice(), b"foo");
+}
+
+#[test]
+fn jittered_constant_delay_varies_within_bounds() {
+    use fluent::sender::JitteredConstantDelay;
+    use std::time::{Duration, Instant};
+
+    let now = Instant::now();
+    let mut waited_less = 0;
+    let mut waited_more = 0;
+    for _ in 0..100 {
+        let mut manager = JitteredConstantDelay::new(Duration::from_millis(100), 0.5);
+        manager.record_error(now);
+
+        assert!(!manager.should_retry(now + Duration::from_millis(49)));
+        assert!(manager.should_retry(now + Duration::from_millis(150)));
+        if manager.should_retry(now + Duration::from_millis(100)) {
+            waited_less += 1;
+        } else {
+            waited_more += 1;
+        }
+    }
+
+    // with 100 instances, all landing on one side of the midpoint is practically impossible
+    assert!(waited_less > 0);
+    assert!(waited_more > 0);
+}
+
+#[test]
+fn token_bucket_retry_refills_over_time() {
+    use fluent::sender::TokenBucketRetry;
+    use std::time::{Duration, Instant};
+
+    let mut manager = TokenBucketRetry::new(3, Duration::from_secs(10));
+    let start = Instant::now();
+    assert_eq!(manager.tokens(start), 3);
+
+    // drain the bucket
+    for _ in 0..3 {
+        assert!(manager.should_retry(start));
+        manager.record_error(start);
+    }
+    assert_eq!(manager.tokens(start), 0);
+    assert!(!manager.should_retry(start + Duration::from_secs(9)));
+
+    // one token per interval
+    assert_eq!(manager.tokens(start + Duration::from_secs(10)), 1);
+    assert_eq!(manager.tokens(start + Duration::from_secs(25)), 2);
+    assert_eq!(manager.tokens(start + Duration::from_secs(300)), 3);
+
+    // spending a token keeps the progress of the partially refilled one
+    manager.record_error(start + Duration::from_secs(15));
+    assert_eq!(manager.tokens(start + Duration::from_secs(19)), 0);
+    assert_eq!(manager.tokens(start + Duration::from_secs(20)), 1);
+    assert_eq!(manager.tokens(start + Duration::from_secs(40)), 3);
+
+    // clear_errors refills the bucket
+    manager.clear_errors();
+    assert_eq!(manager.tokens(start + Duration::from_secs(15)), 3);
+}
+
+#[test]
+fn sampling_sender_keeps_one_in_n() {
+    use fluent::sender::{Sampling, SamplingSender, WriterSender};
+
+    let mut sender = SamplingSender::new(WriterSender::new(Vec::new()), Sampling::OneIn(10));
+    for _ in 0..100 {
+        assert!(sender.emit(b"x").is_ok());
+    }
+
+    assert_eq!(sender.dropped(), 90);
+    assert_eq!(sender.into_inner().into_inner().len(), 10);
+}
+
+#[test]
+fn sampling_sender_limits_rate() {
+    use fluent::sender::{Sampling, SamplingSender, WriterSender};
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let clock = FakeClock(Rc::new(Cell::new(start)));
+    let mut sender = SamplingSender::with_clock(WriterSender::new(Vec::new()), Sampling::PerSecond(5.0), clock.clone());
+
+    // a burst of up to one second's worth
+    for _ in 0..8 {
+        assert!(sender.emit(b"x").is_ok());
+    }
+    assert_eq!(sender.dropped(), 3);
+
+    // refills at the configured rate
+    clock.0.set(start + Duration::from_millis(400));
+    for _ in 0..3 {
+        assert!(sender.emit(b"x").is_ok());
+    }
+    assert_eq!(sender.dropped(), 4);
+    assert_eq!(sender.get_ref().get_ref().len(), 7);
+
+    // less than one per second still lets some through
+    let mut sender = SamplingSender::with_clock(WriterSender::new(Vec::new()), Sampling::PerSecond(0.5), clock.clone());
+    for i in 0..20 {
+        clock.0.set(start + Duration::from_secs(i * 10));
+        assert!(sender.emit(b"x").is_ok());
+        assert!(sender.emit(b"x").is_ok());
+    }
+    assert_eq!(sender.dropped(), 20);
+    assert_eq!(sender.get_ref().get_ref().len(), 20);
+}
+
+#[test]
+fn background_sender_delivers_frames_in_order_per_thread() {
+    use fluent::sender::{BackgroundSender, WriterSender};
+    use std::thread;
+
+    let sender = BackgroundSender::new(WriterSender::new(Vec::new()), 4);
+    let threads: Vec<_> = (0..4).map(|t| {
+        let mut handle = sender.handle();
+        thread::spawn(move || {
+            for n in 0..100 {
+                handle.emit(format!("{} {}\n", t, n).as_bytes()).unwrap();
+            }
+        })
+    }).collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    let mut handle = sender.handle();
+    let written = String::from_utf8(sender.shutdown().unwrap().into_inner()).unwrap();
+    let mut next = vec![0; 4];
+    for line in written.lines() {
+        let mut fields = line.split(' ').map(|f| f.parse::<usize>().unwrap());
+        let (t, n) = (fields.next().unwrap(), fields.next().unwrap());
+        assert_eq!(n, next[t]);
+        next[t] += 1;
+    }
+    assert_eq!(next, vec![100; 4]);
+
+    // the worker is gone
+    assert!(handle.emit(b"foo").is_err());
+}
+
+#[test]
+fn circuit_breaker_transitions() {
+    use fluent::sender::{CircuitBreaker, CircuitState};
+    use std::time::{Duration, Instant};
+
+    let mut manager = CircuitBreaker::new(3, Duration::from_secs(5));
+    let start = Instant::now();
+
+    // Closed -> Open
+    for _ in 0..2 {
+        manager.record_error(start);
+        assert_eq!(manager.state(start), CircuitState::Closed);
+    }
+    manager.record_error(start);
+    assert_eq!(manager.state(start), CircuitState::Open);
+    assert!(!manager.should_retry(start + Duration::from_secs(4)));
+
+    // Open -> HalfOpen, failed probe -> Open
+    let probe = start + Duration::from_secs(5);
+    assert_eq!(manager.state(probe), CircuitState::HalfOpen);
+    manager.record_error(probe);
+    assert_eq!(manager.state(probe), CircuitState::Open);
+    assert!(!manager.should_retry(probe + Duration::from_secs(4)));
+
+    // Open -> HalfOpen, successful probe -> Closed
+    let probe = probe + Duration::from_secs(5);
+    assert!(manager.should_retry(probe));
+    manager.clear_errors();
+    assert_eq!(manager.state(probe), CircuitState::Closed);
+
+    // needs threshold consecutive errors to open again
+    manager.record_error(probe);
+    assert_eq!(manager.state(probe), CircuitState::Closed);
+}
+
+#[test]
+fn tcp_sender_buffers_while_retry_manager_waits() {
+    use fluent::sender::{ConstantDelay, TcpSenderBuilder};
+    use std::io::{ErrorKind, Read};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let clock = FakeClock(Rc::new(Cell::new(start)));
+    let mut retry_manager = ConstantDelay::with_params(10, Duration::from_secs(1));
+    retry_manager.record_error(start);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .build_with_clock(listener.local_addr().unwrap(), retry_manager, NullHandler, clock.clone())
+        .unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+
+    // within the wait window, so only buffered
+    assert!(sender.emit(b"foo").is_ok());
+    let mut received = [0; 6];
+    match stream.read(&mut received) {
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => (),
+        other => panic!("expected nothing to be sent, got {:?}", other),
+    }
+
+    // after the wait window, the buffer is flushed
+    clock.0.set(start + Duration::from_secs(1));
+    assert!(sender.emit(b"bar").is_ok());
+    stream.read_exact(&mut received).unwrap();
+    assert_eq!(&received, b"foobar");
+}
+
+#[test]
+fn tcp_sender_check_reconnects_closed_connection() {
+    use fluent::sender::{ConstantDelay, TcpSender};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut sender = TcpSender::new(addr, ConstantDelay::new(), NullHandler).unwrap();
+
+    let (stream, _) = listener.accept().unwrap();
+    assert!(sender.check().is_ok());
+
+    // peer closes, check reconnects
+    drop(stream);
+    assert!(sender.check().is_ok());
+    let (stream, _) = listener.accept().unwrap();
+
+    // nothing to reconnect to
+    drop(stream);
+    drop(listener);
+    assert!(sender.check().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_sender_check_reconnects_closed_connection() {
+    use fluent::sender::{ConstantDelay, UnixSocketSender};
+    use std::env;
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("fluent-test-{}-check.sock", process::id()));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let mut sender = UnixSocketSender::new(&path, ConstantDelay::new(), NullHandler).unwrap();
+
+    let (stream, _) = listener.accept().unwrap();
+    assert!(sender.check().is_ok());
+
+    // peer closes, check reconnects
+    drop(stream);
+    assert!(sender.check().is_ok());
+    let (stream, _) = listener.accept().unwrap();
+
+    // nothing to reconnect to
+    drop(stream);
+    drop(listener);
+    fs::remove_file(&path).unwrap();
+    assert!(sender.check().is_err());
+}
+
+#[test]
+fn failover_sender_check_reconnects_to_reachable_endpoint() {
+    use fluent::sender::{ConstantDelay, FailoverSender};
+    use std::net::TcpListener;
+
+    let primary = TcpListener::bind("127.0.0.1:0").unwrap();
+    let secondary = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = FailoverSender::new(&[primary.local_addr().unwrap(), secondary.local_addr().unwrap()], ConstantDelay::new(), NullHandler).unwrap();
+
+    let (stream, _) = primary.accept().unwrap();
+    assert!(sender.check().is_ok());
+
+    // primary goes away, check connects to the secondary
+    drop(stream);
+    drop(primary);
+    assert!(sender.check().is_ok());
+    let (stream, _) = secondary.accept().unwrap();
+
+    // nothing to reconnect to
+    drop(stream);
+    drop(secondary);
+    assert!(sender.check().is_err());
+}
+
+#[test]
+fn tcp_sender_retries_reconnect_up_to_configured_attempts() {
+    use fluent::sender::{ConstantDelay, TcpSenderBuilder};
+    use std::io;
+    use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use std::vec;
+
+    // resolves to the listener on the first connect, then to a closed port `failures` times
+    #[derive(Clone, Copy)]
+    struct Flaky<'a> {
+        calls: &'a AtomicUsize,
+        failures: usize,
+        good: SocketAddr,
+        bad: SocketAddr,
+    }
+
+    impl<'a> ToSocketAddrs for Flaky<'a> {
+        type Iter = vec::IntoIter<SocketAddr>;
+        fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst);
+            let addr = if n == 0 || n > self.failures { self.good } else { self.bad };
+            Ok(vec![addr].into_iter())
+        }
+    }
+
+    let bad = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let good = listener.local_addr().unwrap();
+    let builder = TcpSenderBuilder::new().reconnect_delay(Duration::from_millis(1));
+
+    // the first two reconnects fail, the third succeeds
+    let calls = AtomicUsize::new(0);
+    let addr = Flaky { calls: &calls, failures: 2, good, bad };
+    let mut sender = builder.reconnect_attempts(3).build(addr, ConstantDelay::new(), NullHandler).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    drop(stream);
+    assert!(sender.check().is_ok());
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+    drop(sender);
+    drop(listener.accept().unwrap());
+
+    // two attempts are not enough
+    let calls = AtomicUsize::new(0);
+    let addr = Flaky { calls: &calls, failures: 2, good, bad };
+    let mut sender = builder.reconnect_attempts(2).build(addr, ConstantDelay::new(), NullHandler).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    drop(stream);
+    assert!(sender.check().is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn tcp_sender_resolves_address_again_on_reconnect() {
+    use fluent::sender::{ConstantDelay, TcpSender};
+    use std::io;
+    use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::vec;
+
+    // a resolver whose answer changes after the first lookup, like DNS after a failover
+    #[derive(Clone, Copy)]
+    struct Stub<'a> {
+        lookups: &'a AtomicUsize,
+        before: SocketAddr,
+        after: SocketAddr,
+    }
+
+    impl<'a> ToSocketAddrs for Stub<'a> {
+        type Iter = vec::IntoIter<SocketAddr>;
+        fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+            let addr = if self.lookups.fetch_add(1, Ordering::SeqCst) == 0 { self.before } else { self.after };
+            Ok(vec![addr].into_iter())
+        }
+    }
+
+    let old = TcpListener::bind("127.0.0.1:0").unwrap();
+    let new = TcpListener::bind("127.0.0.1:0").unwrap();
+    let lookups = AtomicUsize::new(0);
+    let addr = Stub { lookups: &lookups, before: old.local_addr().unwrap(), after: new.local_addr().unwrap() };
+    let mut sender = TcpSender::new(addr, ConstantDelay::new(), NullHandler).unwrap();
+
+    // old endpoint goes away, the reconnect follows the new answer
+    drop(old.accept().unwrap());
+    drop(old);
+    assert!(sender.check().is_ok());
+    assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    assert!(new.accept().is_ok());
+}
+
+#[test]
+fn tcp_sender_connects_over_ipv6() {
+    use fluent::sender::{ConstantDelay, TcpSender};
+    use std::io::Read;
+    use std::net::{Ipv6Addr, TcpListener};
+
+    // skip where IPv6 loopback is unavailable
+    let listener = match TcpListener::bind("[::1]:0") {
+        Ok(listener) => listener,
+        Err(_) => return,
+    };
+    let port = listener.local_addr().unwrap().port();
+
+    let addr = format!("[::1]:{}", port);
+    let mut sender = TcpSender::new(addr.as_str(), ConstantDelay::new(), NullHandler).unwrap();
+    assert!(sender.emit(b"foo").is_ok());
+    drop(sender);
+    let mut sender = TcpSender::new((Ipv6Addr::LOCALHOST, port), ConstantDelay::new(), NullHandler).unwrap();
+    assert!(sender.emit(b"bar").is_ok());
+    drop(sender);
+
+    for expected in &[b"foo", b"bar"] {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        assert_eq!(&received, expected);
+    }
+}
+
+#[test]
+fn tcp_sender_reconnect_swaps_stream() {
+    use fluent::sender::{ConstantDelay, TcpSender};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut sender = TcpSender::new(addr, ConstantDelay::new(), NullHandler).unwrap();
+    let connected = Arc::new(Mutex::new(Vec::new()));
+    let recorded = connected.clone();
+    sender.set_on_reconnect(move |stream| recorded.lock().unwrap().push(stream.peer_addr().unwrap()));
+    let (mut old, _) = listener.accept().unwrap();
+
+    assert!(sender.reconnect().is_ok());
+    let (mut new, _) = listener.accept().unwrap();
+    assert_eq!(*connected.lock().unwrap(), vec![addr]);
+
+    // the old connection is closed, data goes to the new one
+    assert!(sender.emit(b"foo").is_ok());
+    let mut received = Vec::new();
+    old.read_to_end(&mut received).unwrap();
+    assert!(received.is_empty());
+    drop(sender);
+    new.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"foo");
+}
+
+#[test]
+fn tcp_sender_emit_batch_is_all_or_nothing() {
+    use fluent::sender::TcpSenderBuilder;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .buffer_capacity(8)
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+
+    assert!(sender.emit(b"foo").is_ok());
+
+    // doesn't fit into the remaining 5 bytes, so nothing is buffered
+    match sender.emit_batch(&[b"ab", b"cd", b"ef"]) {
+        Err(SenderError::BufferFull) => (),
+        _ => panic!("expected BufferFull"),
+    }
+    assert_eq!(sender.buffered_len(), 3);
+    assert_eq!(sender.frames_buffered(), 1);
+
+    match sender.emit_batch(&[b"abcde", b"fghij"]) {
+        Err(SenderError::TooLargeData { data_len: 10, capacity: 8 }) => (),
+        _ => panic!("expected TooLargeData"),
+    }
+    assert_eq!(sender.buffered_len(), 3);
+
+    assert!(sender.emit_batch(&[b"ab", b"cd"]).is_ok());
+    let frames: Vec<&[u8]> = sender.pending_frames().collect();
+    assert_eq!(frames, vec![&b"foo"[..], &b"ab"[..], &b"cd"[..]]);
+}
+
+#[test]
+fn writer_sender_reports_timeout() {
+    use fluent::sender::WriterSender;
+    use std::io::{self, Write};
+
+    struct Slow;
+
+    impl Write for Slow {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> { Err(io::ErrorKind::WouldBlock.into()) }
+        fn flush(&mut self) -> io::Result<()> { Err(io::ErrorKind::TimedOut.into()) }
+    }
+
+    let mut sender = WriterSender::new(Slow);
+    match sender.emit(b"foo") {
+        Err(SenderError::Timeout(ref e)) if e.kind() == io::ErrorKind::WouldBlock => (),
+        _ => panic!("expected Timeout"),
+    }
+    match sender.flush() {
+        Err(SenderError::Timeout(ref e)) if e.kind() == io::ErrorKind::TimedOut => (),
+        _ => panic!("expected Timeout"),
+    }
+}
+
+#[test]
+fn tcp_sender_resumes_after_write_timeout_without_reconnecting() {
+    use fluent::sender::TcpSenderBuilder;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .write_timeout(Duration::from_millis(50))
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+
+    // more than the socket buffers hold while nobody reads
+    let data: Vec<u8> = (0..8 * 1024 * 1024).map(|i| i as u8).collect();
+    assert!(sender.emit(&data).is_ok());
+    match sender.flush() {
+        Err(SenderError::Timeout(_)) => (),
+        other => panic!("expected Timeout, got {:?}", other),
+    }
+
+    let reader = ::std::thread::spawn(move || {
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        received
+    });
+    assert!(sender.flush().is_ok());
+    drop(sender);
+
+    // same connection, and no byte was sent twice
+    assert!(reader.join().unwrap() == data);
+    listener.set_nonblocking(true).unwrap();
+    assert!(listener.accept().is_err());
+}
+
+#[test]
+fn tcp_sender_flush_with_timeout_overrides_default() {
+    use fluent::sender::TcpSenderBuilder;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    // no write timeout by default, so a plain flush would block while nobody reads
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+
+    let data: Vec<u8> = (0..8 * 1024 * 1024).map(|i| i as u8).collect();
+    assert!(sender.emit(&data).is_ok());
+    let start = Instant::now();
+    match sender.flush_with_timeout(Duration::from_millis(50)) {
+        Err(SenderError::Timeout(_)) => (),
+        other => panic!("expected Timeout, got {:?}", other),
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let reader = ::std::thread::spawn(move || {
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        received
+    });
+    assert!(sender.flush().is_ok());
+    drop(sender);
+    assert!(reader.join().unwrap() == data);
+}
+
+#[test]
+fn tcp_sender_resends_whole_buffer_after_connection_breaks_mid_write() {
+    use fluent::sender::TcpSenderBuilder;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+    let data: Vec<u8> = (0..8 * 1024 * 1024).map(|i| i as u8).collect();
+    assert!(sender.emit(&data).is_ok());
+
+    // fluentd reads a prefix, then goes away with unread data, which resets the connection
+    let server = thread::spawn(move || {
+        let (mut first, _) = listener.accept().unwrap();
+        let mut prefix = vec![0; 1024];
+        first.read_exact(&mut prefix).unwrap();
+        drop(first);
+
+        let (mut second, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        second.read_to_end(&mut received).unwrap();
+        (prefix, received)
+    });
+    assert!(sender.flush().is_ok());
+    drop(sender);
+
+    // the prefix was received twice
+    let (prefix, received) = server.join().unwrap();
+    assert!(prefix[..] == data[..1024]);
+    assert!(received == data);
+}
+
+#[test]
+fn tcp_sender_socket_options_can_be_reapplied_on_reconnect() {
+    use fluent::sender::{ConstantDelay, TcpSender};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSender::new(listener.local_addr().unwrap(), ConstantDelay::new(), NullHandler).unwrap();
+    let default_ttl = sender.stream().ttl().unwrap();
+
+    sender.stream().set_ttl(42).unwrap();
+    assert_eq!(sender.stream().ttl().unwrap(), 42);
+
+    // a new connection starts with default options
+    assert!(sender.reconnect().is_ok());
+    assert_eq!(sender.stream().ttl().unwrap(), default_ttl);
+
+    sender.set_on_reconnect(|stream| stream.set_ttl(42).unwrap());
+    assert!(sender.reconnect().is_ok());
+    assert_eq!(sender.stream().ttl().unwrap(), 42);
+}
+
+#[test]
+fn tcp_sender_passes_dead_letters_once_per_frame() {
+    use fluent::sender::{DeadLetterHandler, OverflowPolicy, TcpSenderBuilder};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    struct Collect(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl DeadLetterHandler for Collect {
+        fn on_dead_letter(&mut self, tag: Option<&str>, frame: &[u8]) {
+            assert_eq!(tag, None);
+            self.0.lock().unwrap().push(frame.to_vec());
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .buffer_capacity(4)
+        .overflow_policy(OverflowPolicy::DropOldest)
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+    let dead = Arc::new(Mutex::new(Vec::new()));
+    sender.set_dead_letter_handler(Collect(dead.clone()));
+
+    // too large to ever fit
+    assert!(sender.emit(b"12345").is_err());
+    assert_eq!(*dead.lock().unwrap(), vec![b"12345".to_vec()]);
+
+    // evicted to make room
+    assert!(sender.emit(b"ab").is_ok());
+    assert!(sender.emit(b"cd").is_ok());
+    assert!(sender.emit(b"ef").is_ok());
+    assert_eq!(*dead.lock().unwrap(), vec![b"12345".to_vec(), b"ab".to_vec()]);
+
+    // a batch which is only too large as a whole is not dead-lettered
+    assert!(sender.emit_batch(&[b"gh", b"ij", b"k"]).is_err());
+    assert!(sender.emit_batch(&[b"67890", b"l"]).is_err());
+    assert_eq!(*dead.lock().unwrap(), vec![b"12345".to_vec(), b"ab".to_vec(), b"67890".to_vec()]);
+}
+
+#[test]
+fn tcp_sender_reports_metrics() {
+    use fluent::sender::{Metrics, TcpSenderBuilder};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Counts {
+        emitted: usize,
+        flushed: usize,
+        errors: usize,
+        reconnects: usize,
+    }
+
+    struct Recording(Arc<Mutex<Counts>>);
+
+    impl Metrics for Recording {
+        fn on_emit(&mut self, bytes: usize) { self.0.lock().unwrap().emitted += bytes; }
+        fn on_flush(&mut self, bytes: usize) { self.0.lock().unwrap().flushed += bytes; }
+        fn on_error(&mut self, _: &SenderError) { self.0.lock().unwrap().errors += 1; }
+        fn on_reconnect(&mut self) { self.0.lock().unwrap().reconnects += 1; }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .buffer_capacity(4)
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+    let counts = Arc::new(Mutex::new(Counts::default()));
+    sender.set_metrics(Recording(counts.clone()));
+    let (stream, _) = listener.accept().unwrap();
+
+    assert!(sender.emit(b"foo").is_ok());
+    assert!(sender.emit(b"b").is_ok());
+    assert!(sender.emit(b"bar").is_err());
+    assert!(sender.flush().is_ok());
+
+    // peer closes, check reconnects
+    drop(stream);
+    assert!(sender.check().is_ok());
+
+    let counts = counts.lock().unwrap();
+    assert_eq!(counts.emitted, 4);
+    assert_eq!(counts.flushed, 4);
+    assert_eq!(counts.errors, 1);
+    assert_eq!(counts.reconnects, 1);
+}
+
+#[test]
+fn tcp_sender_emit_with_encodes_into_buffer() {
+    use fluent::sender::TcpSenderBuilder;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .buffer_capacity(6)
+        .build(listener.local_addr().unwrap(), AlwaysWait, NullHandler)
+        .unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+
+    assert!(sender.emit_with(|buf| buf.extend_from_slice(b"foo")).is_ok());
+    assert!(sender.emit_with(|buf| buf.extend_from_slice(b"bar")).is_ok());
+    // doesn't fit, so nothing is buffered
+    match sender.emit_with(|buf| buf.extend_from_slice(b"baz")) {
+        Err(SenderError::BufferFull) => (),
+        _ => panic!("expected BufferFull"),
+    }
+    drop(sender);
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"foobar");
+}
+
+#[test]
+fn tcp_sender_emit_with_appends_after_pending_frames() {
+    use fluent::sender::TcpSender;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSender::new(listener.local_addr().unwrap(), AlwaysWait, NullHandler).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+
+    assert!(sender.emit(b"foo").is_ok());
+    // f only sees its own frame
+    assert!(sender.emit_with(|buf| {
+        assert!(buf.is_empty());
+        buf.write_all(b"ba").unwrap();
+        buf.push(b'r');
+        assert_eq!(buf.len(), 3);
+    }).is_ok());
+    let frames: Vec<&[u8]> = sender.pending_frames().collect();
+    assert_eq!(frames, vec![&b"foo"[..], &b"bar"[..]]);
+    drop(sender);
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"foobar");
+}
+
+#[test]
+fn tcp_sender_reports_buffered_data() {
+    use fluent::sender::{ConstantDelay, TcpSender};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut sender = TcpSender::new(addr, AlwaysWait, NullHandler).unwrap();
+    assert!(sender.emit(b"foo").is_ok());
+    assert!(sender.emit(b"barbaz").is_ok());
+    assert_eq!(sender.buffered_len(), 9);
+    assert_eq!(sender.frames_buffered(), 2);
+
+    let mut sender = TcpSender::new(addr, ConstantDelay::new(), NullHandler).unwrap();
+    assert!(sender.emit(b"foo").is_ok());
+    assert_eq!(sender.buffered_len(), 0);
+    assert_eq!(sender.frames_buffered(), 0);
+}
+
+#[test]
+fn tcp_sender_shutdown_waits_for_retry_window() {
+    use fluent::sender::{ConstantDelay, TcpSender};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    let mut retry_manager = ConstantDelay::with_params(10, Duration::from_millis(100));
+    retry_manager.record_error(Instant::now());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSender::new(listener.local_addr().unwrap(), retry_manager, NullHandler).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+
+    assert!(sender.emit(b"foo").is_ok());
+    assert_eq!(sender.buffered_len(), 3);
+
+    assert!(sender.shutdown(Duration::from_secs(5)).is_ok());
+    assert_eq!(sender.buffered_len(), 0);
+    drop(sender);
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"foo");
+}
+
+#[test]
+fn tcp_sender_shutdown_gives_up_after_timeout() {
+    use fluent::sender::TcpSender;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSender::new(listener.local_addr().unwrap(), AlwaysWait, NullHandler).unwrap();
+
+    assert!(sender.emit(b"foo").is_ok());
+    match sender.shutdown(Duration::from_millis(50)) {
+        Err(SenderError::Unflushed(3)) => (),
+        other => panic!("expected Unflushed(3), got {:?}", other),
+    }
+}
+
+#[test]
+fn tcp_sender_shutdown_caps_reconnects_by_timeout() {
+    use fluent::sender::{ConstantDelay, TcpSenderBuilder};
+    use std::net::{Shutdown, TcpListener};
+    use std::time::Duration;
+
+    let mut retry_manager = ConstantDelay::with_params(10, Duration::from_millis(20));
+    retry_manager.record_error(Instant::now());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSenderBuilder::new()
+        .reconnect_attempts(10)
+        .reconnect_delay(Duration::from_millis(100))
+        .build(listener.local_addr().unwrap(), retry_manager, NullHandler)
+        .unwrap();
+    // writes fail and reconnecting is refused
+    sender.stream().shutdown(Shutdown::Write).unwrap();
+    drop(listener);
+
+    assert!(sender.emit(b"foo").is_ok());
+    let start = Instant::now();
+    match sender.shutdown(Duration::from_millis(50)) {
+        Err(SenderError::Unflushed(3)) => (),
+        other => panic!("expected Unflushed(3), got {:?}", other),
+    }
+    // all reconnect attempts would take a second
+    assert!(start.elapsed() < Duration::from_millis(500));
+}
+
+#[test]
+fn tcp_sender_iterates_pending_frames() {
+    use fluent::sender::TcpSender;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender = TcpSender::new(listener.local_addr().unwrap(), AlwaysWait, NullHandler).unwrap();
+
+    assert!(sender.emit(b"a").is_ok());
+    assert!(sender.emit(b"bcd").is_ok());
+    assert!(sender.emit_with(|buf| buf.extend_from_slice(b"ef")).is_ok());
+
+    let frames: Vec<&[u8]> = sender.pending_frames().collect();
+    assert_eq!(frames, vec![&b"a"[..], &b"bcd"[..], &b"ef"[..]]);
+}
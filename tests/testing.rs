@@ -0,0 +1,39 @@
+extern crate fluent;
+
+use fluent::sender::{Sender, SenderError};
+
+#[test]
+fn mock_sender_scripts_emit_blocking_retries() {
+    use fluent::testing::MockSender;
+    use std::time::Duration;
+
+    let mut sender = MockSender::new();
+    sender.push_emit_result(Err(SenderError::BufferFull));
+    sender.push_emit_result(Err(SenderError::BufferFull));
+
+    assert!(sender.emit_blocking(b"foo", 3, Duration::from_millis(1)).is_ok());
+    assert_eq!(sender.emitted(), &[b"foo".to_vec()]);
+    assert_eq!(sender.flushes(), 0);
+}
+
+#[test]
+fn mock_sender_counts_flushes_through_wrappers() {
+    use fluent::sender::{RoundRobinSender, SharedSender};
+    use fluent::testing::MockSender;
+    use std::io;
+
+    let mut failing = MockSender::new();
+    failing.push_flush_result(Err(SenderError::IO(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed"))));
+    let mut sender = RoundRobinSender::new(vec![SharedSender::new(failing), SharedSender::new(MockSender::new())]);
+
+    assert!(sender.emit(b"foo").is_ok());
+    assert!(sender.emit(b"bar").is_ok());
+    assert!(sender.flush().is_err());
+    assert!(sender.flush().is_ok());
+
+    let mocks: Vec<MockSender> = sender.into_inner().into_iter().map(|s| s.into_inner().unwrap()).collect();
+    assert_eq!(mocks[0].emitted(), &[b"foo".to_vec()]);
+    assert_eq!(mocks[1].emitted(), &[b"bar".to_vec()]);
+    assert_eq!(mocks[0].flushes(), 2);
+    assert_eq!(mocks[1].flushes(), 2);
+}